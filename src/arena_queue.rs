@@ -0,0 +1,255 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::mem::MaybeUninit;
+use std::cell::UnsafeCell;
+use std::thread;
+use sync_queue::SyncQueue;
+
+// Nodes are named by their index into the arena rather than by pointer.
+// The low 32 bits of every head, tail, free and next word hold an index, and
+// the high 32 bits hold a modification counter, so a single-word CAS also
+// checks that the slot was not recycled in the meantime.
+const NULL: u32 = u32::MAX;
+
+/// Packs an index and a modification counter into one word.
+fn pack(index: u32, tag: u32) -> u64 {
+    (index as u64) | ((tag as u64) << 32)
+}
+
+fn index_of(word: u64) -> u32 {
+    word as u32
+}
+
+fn tag_of(word: u64) -> u32 {
+    (word >> 32) as u32
+}
+
+/// A node in the arena, either holding an item or sitting on the free list
+pub struct Slot<T> {
+    // The MaybeUninit wrapper allows for empty slots.
+    pub data: UnsafeCell<MaybeUninit<T>>,
+    // Tagged index of the next slot, shared by the queue and the free list.
+    pub next: AtomicU64,
+    // How many of the two poppers a slot waits for are done with it,
+    // as in TaggedQueue: the one that takes its data, and the one that unlinks it.
+    released: AtomicU32,
+}
+
+/// ABA-safe lockfree queue based on the Michael-Scott queue design, backed by an arena
+// This is the counted-pointer algorithm from TaggedQueue, but the nodes live in
+// a slab that is allocated once, up front. Since indices are only 32 bits, the
+// tag gets a full 32 bits too, and this works on any platform with a 64-bit CAS.
+// Slots are never freed while the queue is alive, so a thread holding a stale
+// index can always load its next word safely. The tags make sure its CAS then fails.
+// A slot's data is only read by the popper that won it, and the slot is only
+// recycled once that popper is done reading.
+// Once every slot is in use, pushes have to wait for a pop.
+pub struct ArenaQueue<T> {
+    slots: Box<[Slot<T>]>,
+    head: AtomicU64,
+    tail: AtomicU64,
+    free: AtomicU64,
+}
+
+unsafe impl<T: Send> Send for ArenaQueue<T> {}
+unsafe impl<T: Send> Sync for ArenaQueue<T> {}
+
+impl<T> ArenaQueue<T> {
+    /// Creates a queue holding up to `capacity` items.
+    pub fn new(capacity: usize) -> ArenaQueue<T> {
+        assert!(capacity > 0 && capacity < NULL as usize, "capacity must fit in a 32-bit index");
+        // One extra slot for the sentinel, which starts at index 0.
+        // The rest are chained together on the free list.
+        let n_slots = capacity + 1;
+        let slots: Vec<Slot<T>> = (0..n_slots).map(|i| {
+            let next = if i == 0 || i + 1 == n_slots { NULL } else { i as u32 + 1 };
+            Slot {
+                data: UnsafeCell::new(MaybeUninit::uninit()),
+                next: AtomicU64::new(pack(next, 0)),
+                // The first sentinel has no data to wait for.
+                released: AtomicU32::new(if i == 0 { 1 } else { 0 }),
+            }
+        }).collect();
+        ArenaQueue {
+            slots: slots.into_boxed_slice(),
+            head: AtomicU64::new(pack(0, 0)),
+            tail: AtomicU64::new(pack(0, 0)),
+            free: AtomicU64::new(pack(1, 0)),
+        }
+    }
+
+    fn slot(&self, index: u32) -> &Slot<T> {
+        &self.slots[index as usize]
+    }
+
+    /// Takes a slot off of the free list, if there are any left.
+    fn alloc_slot(&self) -> Option<u32> {
+        loop {
+            let top = self.free.load(Ordering::SeqCst);
+            let index = index_of(top);
+            if index == NULL {
+                return None
+            }
+            // The slot may be taken by someone else in the meantime, but its next
+            // word is always safe to read. The tag catches reuse.
+            let next = self.slot(index).next.load(Ordering::SeqCst);
+            let new_top = pack(index_of(next), tag_of(top).wrapping_add(1));
+            if self.free.compare_exchange(top, new_top, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return Some(index)
+            }
+        }
+    }
+
+    /// Returns a slot to the free list.
+    fn free_slot(&self, index: u32) {
+        let slot = self.slot(index);
+        loop {
+            let top = self.free.load(Ordering::SeqCst);
+            let next = slot.next.load(Ordering::SeqCst);
+            slot.next.store(pack(index_of(top), tag_of(next).wrapping_add(1)), Ordering::SeqCst);
+            let new_top = pack(index, tag_of(top).wrapping_add(1));
+            if self.free.compare_exchange(top, new_top, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return
+            }
+        }
+    }
+
+    /// Marks one of the two poppers as done with a slot, and recycles it if it was the last.
+    fn release_slot(&self, index: u32) {
+        if self.slot(index).released.fetch_add(1, Ordering::SeqCst) == 1 {
+            self.free_slot(index);
+        }
+    }
+
+    /// Pushes `item`, or hands it back if every slot is in use.
+    pub fn try_push(&self, item: T) -> Result<(), T> {
+        let index = match self.alloc_slot() {
+            Some(index) => index,
+            None => return Err(item),
+        };
+        let new_slot = self.slot(index);
+        unsafe { new_slot.data.get().write(MaybeUninit::new(item)) };
+        new_slot.released.store(0, Ordering::Relaxed);
+        let next = new_slot.next.load(Ordering::SeqCst);
+        new_slot.next.store(pack(NULL, tag_of(next).wrapping_add(1)), Ordering::SeqCst);
+
+        let mut tail: u64;
+        loop {
+            tail = self.tail.load(Ordering::SeqCst);
+            let tail_slot = self.slot(index_of(tail));
+
+            // grab the next index and make sure that tail has not changed under us
+            let next = tail_slot.next.load(Ordering::SeqCst);
+            if tail != self.tail.load(Ordering::SeqCst) {
+                continue
+            }
+
+            if index_of(next) == NULL {
+                // if CAS succeeds on the tail's next index, then we can commit our push
+                let new_next = pack(index, tag_of(next).wrapping_add(1));
+                if tail_slot.next.compare_exchange(next, new_next, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                    break
+                }
+            } else {
+                // the tail is lagging behind, so help swing it forward
+                let new_tail = pack(index_of(next), tag_of(tail).wrapping_add(1));
+                let _ = self.tail.compare_exchange(tail, new_tail, Ordering::SeqCst, Ordering::SeqCst);
+            }
+        }
+        // commit our push to the queue
+        let new_tail = pack(index, tag_of(tail).wrapping_add(1));
+        let _ = self.tail.compare_exchange(tail, new_tail, Ordering::SeqCst, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Pushes `item`, yielding until there is a free slot for it.
+    pub fn push(&self, item: T) {
+        let mut item = item;
+        while let Err(rejected) = self.try_push(item) {
+            item = rejected;
+            thread::yield_now();
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        loop {
+            let head = self.head.load(Ordering::SeqCst);
+            let tail = self.tail.load(Ordering::SeqCst);
+
+            // grab the next index and make sure the head hasn't changed
+            let next = self.slot(index_of(head)).next.load(Ordering::SeqCst);
+            if head != self.head.load(Ordering::SeqCst) {
+                continue
+            }
+            let next_index = index_of(next);
+
+            if index_of(head) == index_of(tail) {
+                // if there are no more nodes, the queue is empty
+                if next_index == NULL {
+                    return None
+                }
+                // the tail is lagging behind, so help swing it forward
+                let new_tail = pack(next_index, tag_of(tail).wrapping_add(1));
+                let _ = self.tail.compare_exchange(tail, new_tail, Ordering::SeqCst, Ordering::SeqCst);
+                continue
+            }
+
+            let new_head = pack(next_index, tag_of(head).wrapping_add(1));
+            if self.head.compare_exchange(head, new_head, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                // Next is the sentinel now, but it cannot be recycled until we release it,
+                // so no one else touches its data in the meantime.
+                let data = unsafe { self.slot(next_index).data.get().read().assume_init() };
+                self.release_slot(next_index);
+                self.release_slot(index_of(head));
+                return Some(data)
+            }
+        }
+    }
+}
+
+impl<T> Drop for ArenaQueue<T> {
+    fn drop(&mut self) {
+        // The slots themselves go with the arena.
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T: Send + Sync> SyncQueue<T> for ArenaQueue<T> {
+    fn pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.push(elem)
+    }
+
+    fn try_push(&self, elem: T) -> Result<(), T> {
+        self.try_push(elem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use queue_tests::{check_sequential_fifo, check_concurrent_fifo};
+
+    #[test]
+    fn sequential_fifo() {
+        check_sequential_fifo(&ArenaQueue::new(1000), 1000);
+    }
+
+    #[test]
+    fn full_queue_refuses_pushes() {
+        let queue = ArenaQueue::new(2);
+        assert_eq!(queue.try_push(1), Ok(()));
+        assert_eq!(queue.try_push(2), Ok(()));
+        assert_eq!(queue.try_push(3), Err(3));
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.try_push(3), Ok(()));
+    }
+
+    #[test]
+    fn concurrent_no_loss() {
+        // Small enough that slots are recycled all the time.
+        check_concurrent_fifo(&ArenaQueue::new(64), 4, 4, 20_000, 1);
+    }
+}
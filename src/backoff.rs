@@ -0,0 +1,104 @@
+use std::hint;
+use std::cell::Cell;
+use thread_id;
+
+/// Spinning doubles up to 2^MAX_STEP spins, and stays there.
+const MAX_STEP: u32 = 10;
+
+/// How a lockfree loop waits after losing a race, before it retries
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BackoffType {
+    // Retry right away.
+    #[default]
+    None,
+    // Issue a single spin-loop hint.
+    Spin,
+    // Spin twice as long after every failure.
+    Exponential,
+    // Spin for a random amount of time, at least once, and up to twice as long after every failure.
+    Randomized,
+}
+
+/// Tracks backoff over the retries of one operation
+// Create one per operation, so the delay starts small again on the next.
+pub struct Backoff {
+    kind: BackoffType,
+    step: u32,
+    // xorshift state, only used for randomized backoff.
+    rng: u32,
+}
+
+thread_local! {
+    // How many randomized backoffs this thread has started, to seed the next one with.
+    static SEEDS_TAKEN: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Seeds a randomized backoff differently for every thread and every operation.
+// Each thread keeps its own counter, so there is no shared state to contend on.
+fn next_seed() -> u32 {
+    let count = SEEDS_TAKEN.with(|taken| {
+        let count = taken.get();
+        taken.set(count.wrapping_add(1));
+        count
+    });
+    let id = thread_id::current() as u32 + 1;
+    // Spread the bits out, so nearby ids and counts give unrelated sequences.
+    (id.wrapping_mul(0x9E37_79B9) ^ count.wrapping_mul(0x85EB_CA6B)) | 1
+}
+
+impl Backoff {
+    pub fn new(kind: BackoffType) -> Backoff {
+        // Only randomized backoff needs a seed, so the rest skip the thread-local lookup.
+        let rng = if kind == BackoffType::Randomized { next_seed() } else { 0 };
+        Backoff { kind, step: 0, rng }
+    }
+
+    fn next_random(&mut self) -> u32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng
+    }
+
+    /// Waits after a failed attempt.
+    pub fn spin(&mut self) {
+        let spins = match self.kind {
+            BackoffType::None => return,
+            BackoffType::Spin => 1,
+            BackoffType::Exponential => 1 << self.step,
+            BackoffType::Randomized => (self.next_random() & ((1 << self.step) - 1)).max(1),
+        };
+        for _ in 0..spins {
+            hint::spin_loop();
+        }
+        if self.step < MAX_STEP {
+            self.step += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::sync::{Arc, Barrier};
+
+    #[test]
+    fn seeds_differ_across_threads_and_operations() {
+        // Threads that are alive at the same time hold different ids.
+        let barrier = Arc::new(Barrier::new(8));
+        let handles: Vec<_> = (0..8).map(|_| {
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let seeds = [next_seed(), next_seed()];
+                barrier.wait();
+                seeds
+            })
+        }).collect();
+        let mut seeds: Vec<u32> = handles.into_iter().flat_map(|h| h.join().unwrap()).collect();
+        let n = seeds.len();
+        seeds.sort_unstable();
+        seeds.dedup();
+        assert_eq!(seeds.len(), n);
+    }
+}
@@ -1,6 +1,6 @@
 use std::time::{Instant, Duration};
 use sync_queue::ImplType;
-use kernels::{BenchmarkError, run_workload, WorkloadType};
+use kernels::{BenchmarkError, run_workload, WorkloadType, KernelOptions};
 
 
 pub struct BenchmarkResult {
@@ -8,12 +8,12 @@ pub struct BenchmarkResult {
     pub duration: Duration,
 }
 
-pub fn run_benchmark(n_threads: usize, it: &ImplType, wt: &WorkloadType) -> BenchmarkResult {
+pub fn run_benchmark(n_threads: usize, it: &ImplType, wt: &WorkloadType, opts: &KernelOptions) -> BenchmarkResult {
     let start = Instant::now();
-    let result = run_workload(n_threads, wt, it);
+    let result = run_workload(n_threads, wt, it, opts);
     let duration = start.elapsed();
     BenchmarkResult {
-        result: result,
-        duration: duration,
+        result,
+        duration,
     }
 }
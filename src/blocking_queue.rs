@@ -0,0 +1,259 @@
+use std::hint;
+use std::time::{Duration, Instant};
+use event_count::EventCount;
+use sync_queue::{SyncQueue, QueueHandle};
+
+/// How many times a popper retries, spinning in between, before it parks.
+const SPIN_TRIES: usize = 64;
+
+/// Adds blocking pops to any queue
+// Poppers that find the queue empty spin for a little while, in case an item is
+// about to arrive, and then park on an eventcount. Pushers only notify it,
+// which costs a load unless someone is actually parked.
+pub struct BlockingQueue<T> {
+    queue: Box<dyn SyncQueue<T>>,
+    ready: EventCount,
+}
+
+impl<T> BlockingQueue<T> {
+    pub fn new(queue: Box<dyn SyncQueue<T>>) -> BlockingQueue<T> {
+        BlockingQueue { queue, ready: EventCount::new() }
+    }
+
+    pub fn push(&self, item: T) {
+        self.queue.push(item);
+        self.ready.notify_all();
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.queue.pop()
+    }
+
+    /// Pops an item, waiting for one if the queue is empty.
+    pub fn pop_blocking(&self) -> T {
+        self.pop_until(None, &|| false).unwrap()
+    }
+
+    /// Pops an item, waiting up to `timeout` for one if the queue is empty.
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        self.pop_until(Some(Instant::now() + timeout), &|| false)
+    }
+
+    /// Pops an item, waiting for one unless the queue is empty and `done` holds.
+    /// Whoever makes `done` hold has to call `wake_all` afterwards.
+    pub fn pop_blocking_unless(&self, done: &dyn Fn() -> bool) -> Option<T> {
+        self.pop_until(None, done)
+    }
+
+    /// Like `pop_blocking_unless`, but gives up after `timeout` as well.
+    pub fn pop_timeout_unless(&self, timeout: Duration, done: &dyn Fn() -> bool) -> Option<T> {
+        self.pop_until(Some(Instant::now() + timeout), done)
+    }
+
+    /// Wakes every parked popper, so that it checks the queue again.
+    pub fn wake_all(&self) {
+        self.ready.notify_all();
+    }
+
+    fn pop_until(&self, deadline: Option<Instant>, done: &dyn Fn() -> bool) -> Option<T> {
+        loop {
+            for _ in 0..SPIN_TRIES {
+                if let Some(item) = self.queue.pop() {
+                    return Some(item)
+                }
+                hint::spin_loop();
+            }
+
+            let key = self.ready.prepare_wait();
+            // Something may have been pushed before we registered, so check one last time.
+            if let Some(item) = self.queue.pop() {
+                self.ready.cancel_wait(key);
+                return Some(item)
+            }
+            // Items pushed just before `done` came to hold are still ours to take.
+            if done() {
+                self.ready.cancel_wait(key);
+                return self.queue.pop()
+            }
+            // Don't hold up reclamation while asleep.
+            self.queue.offline();
+            self.ready.wait(key, deadline);
+
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return self.queue.pop()
+            }
+        }
+    }
+}
+
+impl<T: Send + Sync> SyncQueue<T> for BlockingQueue<T> {
+    fn pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.push(elem)
+    }
+
+    fn try_push(&self, elem: T) -> Result<(), T> {
+        self.queue.try_push(elem)?;
+        self.ready.notify_all();
+        Ok(())
+    }
+
+    fn push_batch(&self, items: &mut dyn Iterator<Item = T>) {
+        self.queue.push_batch(items);
+        self.ready.notify_all();
+    }
+
+    fn pop_batch(&self, out: &mut Vec<T>, max: usize) -> usize {
+        self.queue.pop_batch(out, max)
+    }
+
+    fn quiescent(&self) {
+        self.queue.quiescent()
+    }
+
+    fn offline(&self) {
+        self.queue.offline()
+    }
+
+    fn handle(&self) -> Option<Box<dyn QueueHandle<T> + '_>> {
+        let inner = self.queue.handle()?;
+        Some(Box::new(BlockingHandle { inner, ready: &self.ready }))
+    }
+}
+
+/// Runs a queue's handle, while still waking parked poppers
+struct BlockingHandle<'a, T> {
+    inner: Box<dyn QueueHandle<T> + 'a>,
+    ready: &'a EventCount,
+}
+
+impl<'a, T> QueueHandle<T> for BlockingHandle<'a, T> {
+    fn push(&mut self, elem: T) {
+        self.inner.push(elem);
+        self.ready.notify_all();
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.inner.pop()
+    }
+
+    fn try_push(&mut self, elem: T) -> Result<(), T> {
+        self.inner.try_push(elem)?;
+        self.ready.notify_all();
+        Ok(())
+    }
+
+    fn push_batch(&mut self, items: &mut dyn Iterator<Item = T>) {
+        self.inner.push_batch(items);
+        self.ready.notify_all();
+    }
+
+    fn pop_batch(&mut self, out: &mut Vec<T>, max: usize) -> usize {
+        self.inner.pop_batch(out, max)
+    }
+
+    fn refresh(&mut self) {
+        self.inner.refresh()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use dirty_queue::DirtyQueue;
+    use sync_queue::{create_impl, ImplType};
+
+    // Gives a popper time to spin out and park before the test goes on.
+    fn let_park() {
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    #[test]
+    fn parked_popper_wakes_up_for_a_push() {
+        let queues: [Box<dyn SyncQueue<u64>>; 2] = [create_impl(&ImplType::MutexLock), Box::new(DirtyQueue::<u64>::new())];
+        for queue in queues {
+            let queue = BlockingQueue::new(queue);
+            thread::scope(|s| {
+                let popper = s.spawn(|| (0..100).map(|_| queue.pop_blocking()).collect::<Vec<_>>());
+                for i in 0..100 {
+                    if i % 10 == 0 {
+                        let_park();
+                    }
+                    queue.push(i);
+                }
+                assert_eq!(popper.join().unwrap(), (0..100).collect::<Vec<_>>());
+            });
+        }
+    }
+
+    #[test]
+    fn mutex_queue_poppers_never_miss_a_push() {
+        // Unlocking the mutex is only a release, so this relies on the notifier's fence.
+        let queue = BlockingQueue::new(create_impl::<u64>(&ImplType::MutexLock));
+        for _ in 0..20 {
+            thread::scope(|s| {
+                let poppers: Vec<_> = (0..4).map(|_| s.spawn(|| {
+                    (0..250).map(|_| queue.pop_timeout(Duration::from_secs(10))).collect::<Option<Vec<_>>>()
+                })).collect();
+                for pusher in 0..4 {
+                    let queue = &queue;
+                    s.spawn(move || {
+                        for i in 0..250 {
+                            queue.push(pusher * 250 + i);
+                        }
+                    });
+                }
+                let mut popped: Vec<u64> = poppers.into_iter()
+                    .flat_map(|popper| popper.join().unwrap().expect("a popper missed a wakeup"))
+                    .collect();
+                popped.sort_unstable();
+                assert_eq!(popped, (0..1000).collect::<Vec<_>>());
+            });
+        }
+    }
+
+    #[test]
+    fn pop_timeout_gives_up_on_an_empty_queue() {
+        let queue = BlockingQueue::new(Box::new(DirtyQueue::<u64>::new()));
+        let start = Instant::now();
+        assert_eq!(queue.pop_timeout(Duration::from_millis(50)), None);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        queue.push(1);
+        assert_eq!(queue.pop_timeout(Duration::from_millis(50)), Some(1));
+        thread::scope(|s| {
+            let popper = s.spawn(|| queue.pop_timeout(Duration::from_secs(10)));
+            let_park();
+            queue.push(2);
+            assert_eq!(popper.join().unwrap(), Some(2));
+        });
+    }
+
+    #[test]
+    fn done_popper_leaves_once_woken() {
+        let queue = BlockingQueue::new(Box::new(DirtyQueue::<u64>::new()));
+        let done = AtomicBool::new(false);
+        let is_done = || done.load(Ordering::SeqCst);
+        thread::scope(|s| {
+            let blocking = s.spawn(|| queue.pop_blocking_unless(&is_done));
+            let timed = s.spawn(|| queue.pop_timeout_unless(Duration::from_secs(10), &is_done));
+            let_park();
+            done.store(true, Ordering::SeqCst);
+            queue.wake_all();
+            let start = Instant::now();
+            assert_eq!(blocking.join().unwrap(), None);
+            assert_eq!(timed.join().unwrap(), None);
+            assert!(start.elapsed() < Duration::from_secs(5));
+        });
+
+        // Items still in the queue come out before done counts.
+        queue.push(1);
+        assert_eq!(queue.pop_blocking_unless(&is_done), Some(1));
+        assert_eq!(queue.pop_timeout_unless(Duration::from_secs(10), &is_done), None);
+    }
+}
@@ -0,0 +1,169 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::mem::MaybeUninit;
+use std::cell::UnsafeCell;
+use std::thread;
+use sync_queue::SyncQueue;
+
+/// A single cell in the ring buffer
+pub struct Slot<T> {
+    // The sequence number tells producers and consumers whose turn it is:
+    // it equals the position when the slot is free for a push at that position,
+    // and the position + 1 once it holds data for a pop at that position.
+    pub seq: AtomicUsize,
+    // The MaybeUninit wrapper allows for empty slots.
+    pub data: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// Bounded lockfree MPMC queue based on Dmitry Vyukov's design
+// (http://www.1024cores.net/home/lock-free-algorithms/queues/bounded-mpmc-queue)
+// Producers and consumers each claim a position with a CAS on their own counter,
+// so they only contend with each other through the per-slot sequence numbers.
+// Nothing is allocated after construction.
+pub struct BoundedQueue<T> {
+    buffer: Box<[Slot<T>]>,
+    mask: usize,
+    enqueue_pos: AtomicUsize,
+    dequeue_pos: AtomicUsize,
+}
+
+unsafe impl<T: Send> Send for BoundedQueue<T> {}
+unsafe impl<T: Send> Sync for BoundedQueue<T> {}
+
+impl<T> BoundedQueue<T> {
+    /// Creates a queue holding up to `capacity` items, which must be a power of two.
+    pub fn new(capacity: usize) -> BoundedQueue<T> {
+        assert!(capacity >= 2 && capacity.is_power_of_two(), "capacity must be a power of two");
+        let buffer: Vec<Slot<T>> = (0..capacity).map(|i| Slot {
+            seq: AtomicUsize::new(i),
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+        }).collect();
+        BoundedQueue {
+            buffer: buffer.into_boxed_slice(),
+            mask: capacity - 1,
+            enqueue_pos: AtomicUsize::new(0),
+            dequeue_pos: AtomicUsize::new(0),
+        }
+    }
+
+    /// Pushes `item`, or hands it back if the queue is full.
+    pub fn try_push(&self, item: T) -> Result<(), T> {
+        let mut pos = self.enqueue_pos.load(Ordering::SeqCst);
+        let slot = loop {
+            let slot = &self.buffer[pos & self.mask];
+            let seq = slot.seq.load(Ordering::SeqCst);
+            let diff = seq as isize - pos as isize;
+            if diff == 0 {
+                // The slot is free, so try to claim this position.
+                match self.enqueue_pos.compare_exchange_weak(pos, pos + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                    Ok(_) => break slot,
+                    Err(actual) => pos = actual,
+                }
+            } else if diff < 0 {
+                // The slot still holds data from the previous lap, so we are full.
+                return Err(item)
+            } else {
+                // Someone else claimed this position, so catch up.
+                pos = self.enqueue_pos.load(Ordering::SeqCst);
+            }
+        };
+        unsafe { slot.data.get().write(MaybeUninit::new(item)) };
+        // Hand the slot over to the consumer at this position.
+        slot.seq.store(pos + 1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Pushes `item`, yielding until there is room for it.
+    pub fn push(&self, item: T) {
+        let mut item = item;
+        while let Err(rejected) = self.try_push(item) {
+            item = rejected;
+            thread::yield_now();
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let mut pos = self.dequeue_pos.load(Ordering::SeqCst);
+        let slot = loop {
+            let slot = &self.buffer[pos & self.mask];
+            let seq = slot.seq.load(Ordering::SeqCst);
+            let diff = seq as isize - (pos + 1) as isize;
+            if diff == 0 {
+                // The slot is full, so try to claim this position.
+                match self.dequeue_pos.compare_exchange_weak(pos, pos + 1, Ordering::SeqCst, Ordering::SeqCst) {
+                    Ok(_) => break slot,
+                    Err(actual) => pos = actual,
+                }
+            } else if diff < 0 {
+                // Nothing has been pushed here yet, so we are empty.
+                return None
+            } else {
+                // Someone else claimed this position, so catch up.
+                pos = self.dequeue_pos.load(Ordering::SeqCst);
+            }
+        };
+        let result = unsafe { slot.data.get().read().assume_init() };
+        // Hand the slot over to the producer on the next lap.
+        slot.seq.store(pos + self.mask + 1, Ordering::SeqCst);
+        Some(result)
+    }
+}
+
+impl<T> Drop for BoundedQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T: Send + Sync> SyncQueue<T> for BoundedQueue<T> {
+    fn pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.push(elem)
+    }
+
+    fn try_push(&self, elem: T) -> Result<(), T> {
+        self.try_push(elem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use queue_tests::{check_sequential_fifo, check_concurrent_fifo};
+
+    #[test]
+    fn sequential_fifo() {
+        check_sequential_fifo(&BoundedQueue::new(1024), 1000);
+    }
+
+    #[test]
+    fn full_queue_refuses_pushes() {
+        let queue = BoundedQueue::new(2);
+        assert_eq!(queue.try_push(1), Ok(()));
+        assert_eq!(queue.try_push(2), Ok(()));
+        assert_eq!(queue.try_push(3), Err(3));
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.try_push(3), Ok(()));
+    }
+
+    #[test]
+    fn positions_wrap_around() {
+        // Many laps around a small buffer, with the queue never quite empty.
+        let queue = BoundedQueue::new(4);
+        queue.push(0);
+        for i in 1..1000 {
+            assert_eq!(queue.try_push(i), Ok(()));
+            assert_eq!(queue.pop(), Some(i - 1));
+        }
+        assert_eq!(queue.pop(), Some(999));
+        assert_eq!(queue.pop(), None);
+    }
+
+    #[test]
+    fn concurrent_no_loss() {
+        // Small enough that slots are recycled all the time.
+        check_concurrent_fifo(&BoundedQueue::new(64), 4, 4, 20_000, 1);
+    }
+}
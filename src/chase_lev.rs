@@ -0,0 +1,277 @@
+use std::sync::atomic::{AtomicIsize, AtomicPtr, Ordering, fence};
+use std::sync::Arc;
+use std::mem::MaybeUninit;
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
+use std::ptr;
+
+/// Initial number of slots in a deque's buffer.
+const MIN_CAPACITY: usize = 64;
+
+/// A circular array of slots, indexed modulo its capacity
+struct Buffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+impl<T> Buffer<T> {
+    fn new(capacity: usize) -> Buffer<T> {
+        let slots: Vec<UnsafeCell<MaybeUninit<T>>> = (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect();
+        Buffer { slots: slots.into_boxed_slice() }
+    }
+
+    fn capacity(&self) -> isize {
+        self.slots.len() as isize
+    }
+
+    unsafe fn write(&self, i: isize, item: T) {
+        let slot = &self.slots[(i & (self.capacity() - 1)) as usize];
+        slot.get().write(MaybeUninit::new(item));
+    }
+
+    // Thieves may read a slot that is being taken by someone else,
+    // so the copy stays uninitialized until its CAS succeeds.
+    unsafe fn read(&self, i: isize) -> MaybeUninit<T> {
+        let slot = &self.slots[(i & (self.capacity() - 1)) as usize];
+        ptr::read(slot.get())
+    }
+}
+
+/// State shared between a deque's owner and its thieves
+struct Inner<T> {
+    top: AtomicIsize,
+    bottom: AtomicIsize,
+    buffer: AtomicPtr<Buffer<T>>,
+    // Old buffers are kept until the deque is dropped, since thieves may still
+    // be reading from them. The buffer doubles each time, so the old ones add up
+    // to less than the current one, and a deque never holds more than twice the
+    // memory its largest buffer needs. Only the owner touches this list.
+    retired: UnsafeCell<Vec<*mut Buffer<T>>>,
+}
+
+unsafe impl<T: Send> Send for Inner<T> {}
+unsafe impl<T: Send> Sync for Inner<T> {}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let top = *self.top.get_mut();
+        let bottom = *self.bottom.get_mut();
+        unsafe {
+            let buffer = Box::from_raw(*self.buffer.get_mut());
+            for i in top..bottom {
+                drop(buffer.read(i).assume_init());
+            }
+            for old in (*self.retired.get()).drain(..) {
+                drop(Box::from_raw(old));
+            }
+        }
+    }
+}
+
+/// The result of a steal attempt
+pub enum Steal<T> {
+    Empty,
+    Success(T),
+    // We lost a race with another thief or the owner, so the deque may not be empty.
+    Retry,
+}
+
+/// Creates a new work-stealing deque, returning its owner and thief handles.
+pub fn deque<T>() -> (Worker<T>, Stealer<T>) {
+    let inner = Arc::new(Inner {
+        top: AtomicIsize::new(0),
+        bottom: AtomicIsize::new(0),
+        buffer: AtomicPtr::new(Box::into_raw(Box::new(Buffer::new(MIN_CAPACITY)))),
+        retired: UnsafeCell::new(vec![]),
+    });
+    let worker = Worker { inner: inner.clone(), _marker: PhantomData };
+    let stealer = Stealer { inner };
+    (worker, stealer)
+}
+
+/// The owner's end of a Chase-Lev work-stealing deque
+// Based on the Chase-Lev deque (Dynamic Circular Work-Stealing Deque, SPAA 2005),
+// with the memory orderings from Lê et al. (Correct and Efficient Work-Stealing
+// for Weak Memory Models, PPoPP 2013).
+// The owner pushes and pops at the bottom without any CAS, except when racing
+// a thief for the last item. Thieves take from the top with a CAS.
+pub struct Worker<T> {
+    inner: Arc<Inner<T>>,
+    // Only one thread may own the bottom of the deque.
+    _marker: PhantomData<Cell<()>>,
+}
+
+unsafe impl<T: Send> Send for Worker<T> {}
+
+impl<T> Worker<T> {
+    /// Pushes an item onto the bottom, growing the buffer if it is full.
+    /// Outgrown buffers are only freed once the deque is dropped.
+    pub fn push(&self, item: T) {
+        let inner = &*self.inner;
+        let b = inner.bottom.load(Ordering::Relaxed);
+        let t = inner.top.load(Ordering::Acquire);
+        let mut buffer = unsafe { &*inner.buffer.load(Ordering::Relaxed) };
+
+        // Grow the buffer if it is full.
+        if b - t >= buffer.capacity() {
+            buffer = self.grow(buffer, b, t);
+        }
+
+        unsafe { buffer.write(b, item) };
+        fence(Ordering::Release);
+        inner.bottom.store(b + 1, Ordering::Relaxed);
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let inner = &*self.inner;
+        let b = inner.bottom.load(Ordering::Relaxed) - 1;
+        let buffer = unsafe { &*inner.buffer.load(Ordering::Relaxed) };
+        inner.bottom.store(b, Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+        let t = inner.top.load(Ordering::Relaxed);
+
+        if t > b {
+            // The deque was empty, so put bottom back.
+            inner.bottom.store(b + 1, Ordering::Relaxed);
+            return None
+        }
+
+        let item = unsafe { buffer.read(b) };
+        if t == b {
+            // This is the last item, so we have to race thieves for it.
+            let won = inner.top.compare_exchange(t, t + 1, Ordering::SeqCst, Ordering::Relaxed).is_ok();
+            inner.bottom.store(b + 1, Ordering::Relaxed);
+            if !won {
+                return None
+            }
+        }
+        Some(unsafe { item.assume_init() })
+    }
+
+    /// Moves the items into a buffer twice as large.
+    fn grow(&self, old: &Buffer<T>, b: isize, t: isize) -> &Buffer<T> {
+        let new = Buffer::new(old.slots.len() * 2);
+        for i in t..b {
+            unsafe { new.write(i, old.read(i).assume_init()) };
+        }
+        let new = Box::into_raw(Box::new(new));
+        let old = self.inner.buffer.swap(new, Ordering::Release);
+        unsafe {
+            (*self.inner.retired.get()).push(old);
+            &*new
+        }
+    }
+}
+
+/// A thief's end of a Chase-Lev work-stealing deque
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Stealer<T> {
+        Stealer { inner: self.inner.clone() }
+    }
+}
+
+impl<T> Stealer<T> {
+    pub fn steal(&self) -> Steal<T> {
+        let inner = &*self.inner;
+        let t = inner.top.load(Ordering::Acquire);
+        fence(Ordering::SeqCst);
+        let b = inner.bottom.load(Ordering::Acquire);
+        if t >= b {
+            return Steal::Empty
+        }
+
+        let buffer = unsafe { &*inner.buffer.load(Ordering::Acquire) };
+        let item = unsafe { buffer.read(t) };
+        if inner.top.compare_exchange(t, t + 1, Ordering::SeqCst, Ordering::Relaxed).is_err() {
+            return Steal::Retry
+        }
+        Steal::Success(unsafe { item.assume_init() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+    use std::thread;
+    use queue_tests::CountsDrops;
+
+    #[test]
+    fn owner_pops_lifo() {
+        let (worker, _) = deque();
+        for i in 0..10 {
+            worker.push(i);
+        }
+        for i in (0..10).rev() {
+            assert_eq!(worker.pop(), Some(i));
+        }
+        assert_eq!(worker.pop(), None);
+    }
+
+    #[test]
+    fn thieves_steal_fifo() {
+        let (worker, stealer) = deque();
+        for i in 0..10 {
+            worker.push(i);
+        }
+        for i in 0..10 {
+            assert!(matches!(stealer.steal(), Steal::Success(x) if x == i));
+        }
+        assert!(matches!(stealer.steal(), Steal::Empty));
+        assert_eq!(worker.pop(), None);
+    }
+
+    #[test]
+    fn buffer_grows_and_drops_what_is_left() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let n = MIN_CAPACITY * 8 + 1;
+        {
+            let (worker, stealer) = deque();
+            for i in 0..n {
+                worker.push((i, CountsDrops(drops.clone())));
+            }
+            // Take from both ends, so the items have to survive every move.
+            assert!(matches!(stealer.steal(), Steal::Success((0, _))));
+            assert!(matches!(worker.pop(), Some((i, _)) if i == n - 1));
+            assert_eq!(drops.load(Ordering::SeqCst), 2);
+        }
+        assert_eq!(drops.load(Ordering::SeqCst), n);
+    }
+
+    #[test]
+    fn owner_and_thief_race_for_the_last_item() {
+        let n = 100_000;
+        let (worker, stealer) = deque();
+        let done = AtomicBool::new(false);
+        let mut taken = thread::scope(|s| {
+            let thief = s.spawn(|| {
+                let mut stolen = vec![];
+                while !done.load(Ordering::SeqCst) {
+                    if let Steal::Success(i) = stealer.steal() {
+                        stolen.push(i);
+                    }
+                }
+                stolen
+            });
+            // Every pop finds exactly one item, so it always races the thief.
+            let mut popped = vec![];
+            for i in 0..n {
+                worker.push(i);
+                if let Some(i) = worker.pop() {
+                    popped.push(i);
+                }
+            }
+            done.store(true, Ordering::SeqCst);
+            popped.extend(thief.join().unwrap());
+            popped
+        });
+        // Each item went to exactly one of them.
+        taken.sort();
+        assert_eq!(taken, (0..n).collect::<Vec<_>>());
+    }
+}
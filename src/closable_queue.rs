@@ -0,0 +1,314 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+use blocking_queue::BlockingQueue;
+use sync_queue::{SyncQueue, QueueHandle};
+
+/// The result of popping from a closable queue
+#[derive(Debug)]
+pub enum Pop<T> {
+    Item(T),
+    // Nothing is in the queue right now, but more may still be pushed.
+    Empty,
+    // Nothing is in the queue, and nothing more ever will be.
+    Closed,
+}
+
+/// Returned by a blocking pop once the queue is closed and drained
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Closed;
+
+// Gives out unique queue ids, since a new queue may reuse an old one's address.
+static NEXT_QUEUE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // This thread's in-flight flag for every closable queue it has pushed to, by queue id.
+    static PUSHING: RefCell<HashMap<usize, Arc<AtomicBool>>> = RefCell::new(HashMap::new());
+}
+
+/// Lets any queue be closed, so consumers know when to stop
+// Every thread raises its own in-flight flag while it pushes, and only then
+// checks whether the queue is closed. Closing sets the closed flag, and then
+// waits for every raised flag to drop, so each push either lands before the
+// queue counts as closed, or is refused. Pushes only write to their own
+// thread's flag, so they don't contend on anything beyond the queue.
+// Once closed, pushes are refused, and pops report Closed as soon as the queue
+// is drained. Pops block as in BlockingQueue, and wake up when the queue is closed.
+pub struct ClosableQueue<T> {
+    queue: BlockingQueue<T>,
+    id: usize,
+    // The in-flight flag of every thread that has pushed to the queue.
+    pushers: Mutex<Vec<Arc<AtomicBool>>>,
+    closed: AtomicBool,
+    // Set once every push that got in ahead of close has landed.
+    sealed: AtomicBool,
+}
+
+impl<T: Send + Sync> ClosableQueue<T> {
+    pub fn new(queue: Box<dyn SyncQueue<T>>) -> ClosableQueue<T> {
+        ClosableQueue {
+            queue: BlockingQueue::new(queue),
+            id: NEXT_QUEUE.fetch_add(1, Ordering::Relaxed),
+            pushers: Mutex::new(vec![]),
+            closed: AtomicBool::new(false),
+            sealed: AtomicBool::new(false),
+        }
+    }
+
+    /// Refuses every push from now on, once pushes already under way have landed.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        // Threads that register from here on find the queue closed before they push.
+        for pushing in self.pushers.lock().unwrap().iter() {
+            while pushing.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+        }
+        self.sealed.store(true, Ordering::SeqCst);
+        self.queue.wake_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Whether the queue is closed, and every push it let in has landed.
+    fn is_sealed(&self) -> bool {
+        self.sealed.load(Ordering::SeqCst)
+    }
+
+    /// Returns the current thread's in-flight flag, registering it on first use.
+    fn pushing(&self) -> &AtomicBool {
+        let cached = PUSHING.try_with(|pushing| {
+            let mut pushing = pushing.borrow_mut();
+            if let Some(flag) = pushing.get(&self.id) {
+                return Arc::as_ptr(flag)
+            }
+            // Let go of flags for queues that have since been dropped.
+            pushing.retain(|_, flag| Arc::strong_count(flag) > 1);
+            let flag = self.register();
+            let ptr = Arc::as_ptr(&flag);
+            pushing.insert(self.id, flag);
+            ptr
+        });
+        // While the thread is exiting, fall back to a flag just for this push.
+        let flag = cached.unwrap_or_else(|_| Arc::as_ptr(&self.register()));
+        // Every registered flag is kept alive by the queue itself.
+        unsafe { &*flag }
+    }
+
+    fn register(&self) -> Arc<AtomicBool> {
+        let flag = Arc::new(AtomicBool::new(false));
+        self.pushers.lock().unwrap().push(flag.clone());
+        flag
+    }
+
+    /// Runs `push` with `pushing` raised, unless the queue is closed.
+    fn push_with<U, F: FnOnce(U) -> Result<(), U>>(&self, pushing: &AtomicBool, items: U, push: F) -> Result<(), U> {
+        pushing.store(true, Ordering::SeqCst);
+        let result = if self.is_closed() { Err(items) } else { push(items) };
+        // Only close reads this, and it just has to see the push land before it.
+        pushing.store(false, Ordering::Release);
+        result
+    }
+
+    /// Pushes an item, or hands it back if the queue is closed.
+    pub fn push(&self, item: T) -> Result<(), T> {
+        self.push_with(self.pushing(), item, |item| {
+            self.queue.push(item);
+            Ok(())
+        })
+    }
+
+    pub fn pop(&self) -> Pop<T> {
+        if let Some(item) = self.queue.pop() {
+            return Pop::Item(item)
+        }
+        if !self.is_sealed() {
+            return Pop::Empty
+        }
+        // The last pushes may have landed between the pop and the check.
+        match self.queue.pop() {
+            Some(item) => Pop::Item(item),
+            None => Pop::Closed,
+        }
+    }
+
+    /// Pops an item, waiting for one while the queue is empty but still open.
+    pub fn pop_blocking(&self) -> Result<T, Closed> {
+        self.queue.pop_blocking_unless(&|| self.is_sealed()).ok_or(Closed)
+    }
+
+    /// Pops an item, waiting up to `timeout` for one while the queue is empty but still open.
+    pub fn pop_timeout(&self, timeout: Duration) -> Pop<T> {
+        match self.queue.pop_timeout_unless(timeout, &|| self.is_sealed()) {
+            Some(item) => Pop::Item(item),
+            None if self.is_sealed() => Pop::Closed,
+            None => Pop::Empty,
+        }
+    }
+}
+
+impl<T: Send + Sync> SyncQueue<T> for ClosableQueue<T> {
+    fn pop(&self) -> Option<T> {
+        match self.pop() {
+            Pop::Item(item) => Some(item),
+            Pop::Empty | Pop::Closed => None,
+        }
+    }
+
+    // Plain pushes have no way to hand items back, so they drop what a closed queue refuses.
+    fn push(&self, elem: T) {
+        let _ = self.push(elem);
+    }
+
+    fn try_push(&self, elem: T) -> Result<(), T> {
+        self.push_with(self.pushing(), elem, |elem| self.queue.try_push(elem))
+    }
+
+    fn push_batch(&self, items: &mut dyn Iterator<Item = T>) {
+        let _ = self.push_with(self.pushing(), items, |items| {
+            self.queue.push_batch(items);
+            Ok(())
+        });
+    }
+
+    fn pop_batch(&self, out: &mut Vec<T>, max: usize) -> usize {
+        self.queue.pop_batch(out, max)
+    }
+
+    fn quiescent(&self) {
+        self.queue.quiescent()
+    }
+
+    fn offline(&self) {
+        self.queue.offline()
+    }
+
+    fn handle(&self) -> Option<Box<dyn QueueHandle<T> + '_>> {
+        let inner = self.queue.handle()?;
+        Some(Box::new(ClosableHandle { queue: self, inner, pushing: self.pushing() }))
+    }
+}
+
+/// Runs a queue's handle, while still refusing pushes once the queue is closed
+struct ClosableHandle<'a, T> {
+    queue: &'a ClosableQueue<T>,
+    inner: Box<dyn QueueHandle<T> + 'a>,
+    // The thread's in-flight flag, looked up once for the whole handle.
+    pushing: &'a AtomicBool,
+}
+
+impl<'a, T: Send + Sync> QueueHandle<T> for ClosableHandle<'a, T> {
+    fn push(&mut self, elem: T) {
+        let inner = &mut self.inner;
+        let _ = self.queue.push_with(self.pushing, elem, |elem| {
+            inner.push(elem);
+            Ok(())
+        });
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.inner.pop()
+    }
+
+    fn try_push(&mut self, elem: T) -> Result<(), T> {
+        let inner = &mut self.inner;
+        self.queue.push_with(self.pushing, elem, |elem| inner.try_push(elem))
+    }
+
+    fn push_batch(&mut self, items: &mut dyn Iterator<Item = T>) {
+        let inner = &mut self.inner;
+        let _ = self.queue.push_with(self.pushing, items, |items| {
+            inner.push_batch(items);
+            Ok(())
+        });
+    }
+
+    fn pop_batch(&mut self, out: &mut Vec<T>, max: usize) -> usize {
+        self.inner.pop_batch(out, max)
+    }
+
+    fn refresh(&mut self) {
+        self.inner.refresh()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use dirty_queue::DirtyQueue;
+
+    fn closable() -> ClosableQueue<u64> {
+        ClosableQueue::new(Box::new(DirtyQueue::<u64>::new()))
+    }
+
+    #[test]
+    fn closed_queue_refuses_pushes_and_drains() {
+        let queue = closable();
+        assert!(matches!(queue.pop(), Pop::Empty));
+        assert_eq!(queue.push(1), Ok(()));
+        assert_eq!(queue.push(2), Ok(()));
+        assert!(matches!(queue.pop(), Pop::Item(1)));
+        queue.close();
+        assert_eq!(queue.push(3), Err(3));
+        assert_eq!(SyncQueue::try_push(&queue, 4), Err(4));
+        SyncQueue::push(&queue, 5);
+        assert_eq!(queue.pop_blocking(), Ok(2));
+        assert!(matches!(queue.pop(), Pop::Closed));
+        assert_eq!(queue.pop_blocking(), Err(Closed));
+        assert!(matches!(queue.pop_timeout(Duration::from_secs(10)), Pop::Closed));
+    }
+
+    #[test]
+    fn close_wakes_parked_poppers() {
+        let queue = closable();
+        thread::scope(|s| {
+            let poppers: Vec<_> = (0..4).map(|_| s.spawn(|| {
+                let mut popped = vec![];
+                while let Ok(x) = queue.pop_blocking() {
+                    popped.push(x);
+                }
+                popped
+            })).collect();
+            for i in 0..1000 {
+                queue.push(i).unwrap();
+            }
+            queue.close();
+            let mut popped: Vec<u64> = poppers.into_iter().flat_map(|p| p.join().unwrap()).collect();
+            popped.sort_unstable();
+            assert_eq!(popped, (0..1000).collect::<Vec<_>>());
+        });
+    }
+
+    #[test]
+    fn close_waits_for_pushes_in_flight() {
+        // Every push the queue accepts has to come out before poppers see it closed.
+        for _ in 0..20 {
+            let queue = closable();
+            thread::scope(|s| {
+                let pushers: Vec<_> = (0..4).map(|p| {
+                    let queue = &queue;
+                    s.spawn(move || (0..).take_while(|&i| queue.push(p << 32 | i).is_ok()).count())
+                }).collect();
+                let poppers: Vec<_> = (0..2).map(|_| s.spawn(|| {
+                    let mut popped = 0;
+                    while queue.pop_blocking().is_ok() {
+                        popped += 1;
+                    }
+                    popped
+                })).collect();
+                thread::sleep(Duration::from_millis(5));
+                queue.close();
+                let pushed: usize = pushers.into_iter().map(|p| p.join().unwrap()).sum();
+                let popped: usize = poppers.into_iter().map(|p| p.join().unwrap()).sum();
+                assert_eq!(popped, pushed);
+            });
+            assert!(matches!(queue.pop(), Pop::Closed));
+        }
+    }
+}
@@ -5,6 +5,12 @@
 //!
 extern crate clap;
 use sync_queue::ImplType;
+use reclaim::ReclaimType;
+use ebr::EbrConfig;
+use backoff::BackoffType;
+use memory_order::OrderType;
+use padding::PaddingType;
+use dirty_queue::DirtyReclaim;
 use clap::{Arg, App};
 
 #[derive(Clone, Debug)]
@@ -13,6 +19,16 @@ pub struct CmdOptions {
     pub benchmark: String,
     pub verbosity: usize,
     pub n_threads: usize,
+    pub capacity: usize,
+    pub use_handles: bool,
+    pub use_pool: bool,
+    pub batch_size: usize,
+}
+
+impl Default for CmdOptions {
+    fn default() -> CmdOptions {
+        CmdOptions::new()
+    }
 }
 
 impl CmdOptions {
@@ -27,6 +43,14 @@ impl CmdOptions {
         let default_bench = "all";
         let default_verbosity = "0";
         let default_nthreads = "16";
+        let default_capacity = "2097152";
+        let default_reclaim = "epoch";
+        let default_unroll = "32";
+        let default_backoff = "none";
+        let default_ordering = "seqcst";
+        let default_padding = "none";
+        let default_dirty_reclaim = "leak";
+        let default_batch = "1";
 
         let matches = App::new("rust-lockfree")
             .version("0.1.0")
@@ -37,13 +61,22 @@ impl CmdOptions {
                     .required(false)
                     .takes_value(true)
                     .help("specifies the implementation to evaluate
-                          \n\toptions include mutex, spin, lockfree, crossbeam, dirty, and epoch"))
+                          \n\toptions include mutex, spin, twolock, twolockspin, combining, lockfree, crossbeam, dirty, epoch, hazard, ms, qsbr, tagged (x86_64 Linux only), lcrq (x86_64 only), and waitfree
+                          \n\tms is a Michael-Scott queue that uses the reclamation scheme chosen with -r,
+                          \n\tand epoch can use either of the epoch-based schemes
+                          \n\tqsbr is a Michael-Scott queue whose threads only pass quiescent states between items
+                          \n\tunrolled is a Michael-Scott queue holding several items per node, using the scheme chosen with -r
+                          \n\tbounded options include bounded, crossbeamarray, and arena (a Michael-Scott queue over a preallocated slab)
+                          \n\tstack options include mutexstack, lockfreestack, and treiber
+                          \n\tchaselev uses per-worker work-stealing deques, and only runs forkjoin
+                          \n\tchannel options include stdmpsc, spsc, spmc, mpsc, and mpmc,
+                          \n\twhich only run the benchmarks their producer/consumer counts allow"))
             .arg(Arg::with_name("bench")
                     .short("b")
                     .required(false)
                     .takes_value(true)
                     .help("specifies the benchmark to run
-                          \n\toptions include read, write, mixed, mem, and all"))
+                          \n\toptions include read, write, mixed, mem, forkjoin, pairs, and all"))
             .arg(Arg::with_name("verbose")
                     .short("v")
                         .required(false)
@@ -54,21 +87,179 @@ impl CmdOptions {
                         .required(false)
                         .takes_value(true)
                         .help("Number of threads to use, must be even (default: 16)"))
+            .arg(Arg::with_name("capacity")
+                    .short("c")
+                        .required(false)
+                        .takes_value(true)
+                        .help("Capacity of the bounded, arena, and crossbeamarray queues (default: 2097152)
+                              \n\tmust be a power of two for bounded. In the forkjoin benchmark, workers run subtasks
+                              \n\tthat don't fit straight away, so any capacity works, but small ones share less work"))
+            .arg(Arg::with_name("reclaim")
+                    .short("r")
+                        .required(false)
+                        .takes_value(true)
+                        .help("Memory reclamation scheme for the ms, unrolled, and epoch queues (default: epoch)
+                              \n\toptions include leak, epoch (crossbeam's global collector), owned (a crossbeam collector owned by the queue), ebr (the in-crate collector), hazard, and qsbr"))
+            .arg(Arg::with_name("ebr_advance")
+                    .long("ebr-advance")
+                        .required(false)
+                        .takes_value(true)
+                        .help("Pins between attempts to advance the epoch, for -r ebr (default: 128)"))
+            .arg(Arg::with_name("ebr_batch")
+                    .long("ebr-batch")
+                        .required(false)
+                        .takes_value(true)
+                        .help("Retired nodes a thread collects before freeing them, for -r ebr (default: 64)"))
+            .arg(Arg::with_name("unroll")
+                    .long("unroll")
+                        .required(false)
+                        .takes_value(true)
+                        .help("Items per node for the unrolled queue, one of 8, 16, 32, 64, or 128 (default: 32)"))
+            .arg(Arg::with_name("backoff")
+                    .long("backoff")
+                        .required(false)
+                        .takes_value(true)
+                        .help("How the dirty and epoch queues wait before retrying a failed CAS (default: none)
+                              \n\toptions include none, spin (a single spin-loop hint), exp (exponential), and random (randomized exponential)"))
+            .arg(Arg::with_name("ordering")
+                    .long("ordering")
+                        .required(false)
+                        .takes_value(true)
+                        .help("Memory orderings for the dirty and epoch queues' atomics (default: seqcst)
+                              \n\toptions include seqcst and acqrel (the weakest orderings that are still correct)"))
+            .arg(Arg::with_name("padding")
+                    .long("padding")
+                        .required(false)
+                        .takes_value(true)
+                        .help("What the dirty and epoch queues pad out to cachelines (default: none)
+                              \n\toptions include none, nodes, ends (the head and tail, apart from each other), and all"))
+            .arg(Arg::with_name("dirty_reclaim")
+                    .long("dirty-reclaim")
+                        .required(false)
+                        .takes_value(true)
+                        .help("What the dirty queue does with popped nodes (default: leak)
+                              \n\toptions include leak (never free them) and deferred (free them once the queue is dropped)
+                              \n\tneither frees anything during a run, so memory grows with every pop until the benchmark ends"))
+            .arg(Arg::with_name("batch")
+                    .long("batch")
+                        .required(false)
+                        .takes_value(true)
+                        .help("Items each thread pushes or pops at once in the read, write, mixed, and mem benchmarks (default: 1)
+                              \n\tqueues that can do better than one operation per item batch them up"))
+            .arg(Arg::with_name("handles")
+                    .long("handles")
+                        .required(false)
+                        .help("Run queue operations in the read, write, mixed, and mem benchmarks
+                              \n\tthrough per-thread handles, for queues that have them (like epoch)"))
+            .arg(Arg::with_name("pool")
+                    .long("pool")
+                        .required(false)
+                        .help("Draw dirty and epoch queue nodes from a recycling node pool,
+                              \n\tand report how many nodes were allocated and reused"))
             .get_matches();
 
         let impl_name = matches.value_of("impl").unwrap_or(default_impl);
+        let capacity = matches.value_of("capacity").unwrap_or(default_capacity).parse::<usize>().unwrap();
+        if capacity == 0 {
+            panic!("Capacity must be at least one");
+        }
+        let reclaim = match matches.value_of("reclaim").unwrap_or(default_reclaim).to_lowercase().as_str() {
+            "leak" => ReclaimType::Leak,
+            "epoch" => ReclaimType::Epoch,
+            "owned" => ReclaimType::OwnedEpoch,
+            "hazard" => ReclaimType::Hazard,
+            "qsbr" => ReclaimType::Qsbr,
+            "ebr" => {
+                let defaults = EbrConfig::default();
+                let advance_every = matches.value_of("ebr_advance").map_or(defaults.advance_every, |s| s.parse::<usize>().unwrap());
+                let batch_size = matches.value_of("ebr_batch").map_or(defaults.batch_size, |s| s.parse::<usize>().unwrap());
+                if advance_every == 0 {
+                    panic!("EBR must try to advance the epoch at least every so often");
+                }
+                ReclaimType::Ebr(EbrConfig { advance_every, batch_size })
+            },
+            _ => panic!("Invalid choice of reclamation scheme!"),
+        };
+        let backoff = match matches.value_of("backoff").unwrap_or(default_backoff).to_lowercase().as_str() {
+            "none" => BackoffType::None,
+            "spin" => BackoffType::Spin,
+            "exp" => BackoffType::Exponential,
+            "random" => BackoffType::Randomized,
+            _ => panic!("Invalid choice of backoff strategy!"),
+        };
+        let ordering = match matches.value_of("ordering").unwrap_or(default_ordering).to_lowercase().as_str() {
+            "seqcst" => OrderType::SeqCst,
+            "acqrel" => OrderType::AcqRel,
+            _ => panic!("Invalid choice of memory ordering!"),
+        };
+        let padding = match matches.value_of("padding").unwrap_or(default_padding).to_lowercase().as_str() {
+            "none" => PaddingType::None,
+            "nodes" => PaddingType::Nodes,
+            "ends" => PaddingType::Ends,
+            "all" => PaddingType::All,
+            _ => panic!("Invalid choice of padding!"),
+        };
+        let dirty_reclaim = match matches.value_of("dirty_reclaim").unwrap_or(default_dirty_reclaim).to_lowercase().as_str() {
+            "leak" => DirtyReclaim::Leak,
+            "deferred" => DirtyReclaim::Deferred,
+            _ => panic!("Invalid choice of dirty queue reclamation!"),
+        };
         let impl_type = match impl_name.to_lowercase().as_str() {
             "mutex" => ImplType::MutexLock,
             "spin" => ImplType::SpinLock,
+            "twolock" => ImplType::TwoLockMutex,
+            "twolockspin" => ImplType::TwoLockSpin,
+            "combining" => ImplType::FlatCombining,
             "lockfree" => ImplType::Lockfree,
             "crossbeam" => ImplType::Crossbeam,
-            "dirty" => ImplType::Dirty,
-            "epoch" => ImplType::Epoch,
+            "dirty" => ImplType::Dirty(dirty_reclaim, backoff, ordering, padding),
+            "epoch" => match reclaim {
+                ReclaimType::Epoch | ReclaimType::OwnedEpoch | ReclaimType::Ebr(_) => ImplType::Epoch(reclaim, backoff, ordering, padding),
+                _ => panic!("The epoch queue needs an epoch-based reclamation scheme!"),
+            },
+            "hazard" => ImplType::Hazard,
+            "ms" => ImplType::MichaelScott(reclaim),
+            "qsbr" => ImplType::Qsbr,
+            "unrolled" => {
+                let slots = matches.value_of("unroll").unwrap_or(default_unroll).parse::<usize>().unwrap();
+                if ![8, 16, 32, 64, 128].contains(&slots) {
+                    panic!("Unrolled nodes must hold 8, 16, 32, 64, or 128 items");
+                }
+                ImplType::Unrolled(reclaim, slots)
+            },
+            #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+            "tagged" => ImplType::Tagged,
+            #[cfg(target_arch = "x86_64")]
+            "lcrq" => ImplType::Lcrq,
+            "waitfree" => ImplType::WaitFree,
+            "chaselev" => ImplType::ChaseLev,
+            "stdmpsc" => ImplType::StdMpsc,
+            "spsc" => ImplType::LockfreeSpsc,
+            "spmc" => ImplType::LockfreeSpmc,
+            "mpsc" => ImplType::LockfreeMpsc,
+            "mpmc" => ImplType::LockfreeMpmc,
+            "bounded" => {
+                if capacity < 2 || !capacity.is_power_of_two() {
+                    panic!("The bounded queue's capacity must be a power of two");
+                }
+                ImplType::Bounded(capacity)
+            },
+            "crossbeamarray" => ImplType::CrossbeamArray(capacity),
+            "arena" => ImplType::Arena(capacity),
+            "mutexstack" => ImplType::MutexStack,
+            "lockfreestack" => ImplType::LockfreeStack,
+            "treiber" => ImplType::Treiber,
             _ => panic!("Invalid choice of implementation type!"),
         };
 
         let benchmark = matches.value_of("bench").unwrap_or(default_bench).to_string();
         let verbosity = matches.value_of("verbose").unwrap_or(default_verbosity).parse::<usize>().unwrap();
+        let use_handles = matches.is_present("handles");
+        let use_pool = matches.is_present("pool");
+        let batch_size = matches.value_of("batch").unwrap_or(default_batch).parse::<usize>().unwrap();
+        if batch_size == 0 {
+            panic!("Batch size must be at least 1");
+        }
         let n_threads = matches.value_of("n_threads").unwrap_or(default_nthreads).parse::<usize>().unwrap();
 
         if n_threads % 2 != 0 || n_threads <= 1  || n_threads > 16 {
@@ -76,10 +267,14 @@ impl CmdOptions {
         }
 
         CmdOptions {
-            impl_type: impl_type,
-            benchmark: benchmark,
-            verbosity: verbosity,
-            n_threads: n_threads,
+            impl_type,
+            benchmark,
+            verbosity,
+            n_threads,
+            capacity,
+            use_handles,
+            use_pool,
+            batch_size,
         }
     }
 }
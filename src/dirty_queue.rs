@@ -1,115 +1,152 @@
 use std::sync::atomic::{AtomicPtr, Ordering};
-use std::ptr;
-use std::mem::MaybeUninit;
-use std::cell::UnsafeCell;
-use sync_queue::SyncQueue;
-
-/// Stores data and next pointers for items in the queue
-// This will align nodes to cachelines, to avoid false sharing between cores.
-//   Experiments seem to show that this hurts performance for some reason.
-//#[repr(align(64))]
-pub struct Node<T> {
-    // The UnsafeCell wrapper is required to allow unsafe operations on this data,
-    // specifically moving the T object out of raw pointers.
-    // The MaybeUninit wrapper allows for uninitialized nodes to be created.
-    pub data: UnsafeCell<MaybeUninit<T>>,
-    // This pointer to the next node is atomic to allow CAS.
-    pub next: AtomicPtr<Node<T>>,
-}
+use crossbeam_queue::SegQueue;
+use reclaim::{Reclaimer, ReclaimGuard, Retired, Leak};
+use memory_order::{MemoryOrder, SeqCstOrder};
+use padding::{Padding, NoPadding};
+use ms_queue::MsQueue;
 
-impl<T> Node<T> {
-    fn new() -> Node<T> {
-        // Safe to leave MaybeUninit because data is MaybeUninit, 
-        // and the next pointer will be initialized to null.
-        unsafe { MaybeUninit::zeroed().assume_init() }
-    }
+/// What a dirty queue does with the nodes it pops
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DirtyReclaim {
+    // Never free them, which is how the queue started out, and what it is measured with.
+    #[default]
+    Leak,
+    // Keep them on a list, to be freed once nothing can be using the queue.
+    Deferred,
 }
 
-/// Dirty lockfree queue based on the Michael-Scott queue design
-// Reference counting is difficult to implement in Rust, since there are no 
+/// Dirty lockfree Michael-Scott queue, which never frees popped nodes
+// Reference counting is difficult to implement in Rust, since there are no
 // double-word CAS. This approach is based off of a blog post by Christian Hergert.
 // (http://www.hergert.me/blog/2009/12/25/intro-to-lock-free-wait-free-and-aba.html)
-pub struct DirtyQueue<T> {
-    head: AtomicPtr<Node<T>>,
-    tail: AtomicPtr<Node<T>>,
+// Since other threads may still be reading a popped node, nothing is freed
+// while the queue is shared. Items left in the queue are dropped along with it.
+pub type DirtyQueue<T, O = SeqCstOrder, P = NoPadding> = MsQueue<T, Leak, O, P>;
+
+/// Dirty lockfree Michael-Scott queue, which frees popped nodes once no one can be using it
+pub type DeferredDirtyQueue<T, O = SeqCstOrder, P = NoPadding> = MsQueue<T, Deferred, O, P>;
+
+/// Keeps every retired node until the queue is dropped, or `reclaim` is called
+// Nothing is freed while the queue is shared, so memory grows with every pop
+// for as long as the queue is in use. Deferred nodes only go back to the node
+// pool when they are freed, so a running queue mostly allocates fresh ones.
+#[derive(Default)]
+pub struct Deferred {
+    retired: SegQueue<Retired>,
 }
 
-impl<T> DirtyQueue<T> {
-    pub fn new() -> DirtyQueue<T> {
-        // Initializes the queue with an empty node. This makes the push/pop
-        // logic much simpler.
-        let empty_node = Box::into_raw(Box::new(Node::new()));
-        DirtyQueue {
-            head: AtomicPtr::new(empty_node),
-            tail: AtomicPtr::new(empty_node),
+// Retired nodes are only freed through &mut self, when no other thread can reach them.
+unsafe impl Send for Deferred {}
+unsafe impl Sync for Deferred {}
+
+impl Deferred {
+    /// Frees every node retired so far.
+    // Taking &mut self makes this a quiescent point: no other thread can be
+    // in the middle of an operation, so none of them can hold a retired node.
+    pub fn reclaim(&mut self) {
+        while let Ok(r) = self.retired.pop() {
+            unsafe { r.free() };
         }
     }
+}
+
+impl Drop for Deferred {
+    fn drop(&mut self) {
+        self.reclaim();
+    }
+}
+
+pub struct DeferredGuard<'a>(&'a Deferred);
+
+impl Reclaimer for Deferred {
+    type Guard<'a> = DeferredGuard<'a>;
+
+    fn pin(&self) -> DeferredGuard<'_> {
+        DeferredGuard(self)
+    }
+}
+
+impl<'a> ReclaimGuard for DeferredGuard<'a> {
+    fn protect<T>(&self, _slot: usize, src: &AtomicPtr<T>) -> *mut T {
+        src.load(Ordering::SeqCst)
+    }
+
+    unsafe fn defer_free(&self, r: Retired) {
+        self.0.retired.push(r)
+    }
+}
+
+impl<T, O: MemoryOrder, P: Padding> MsQueue<T, Deferred, O, P> {
+    /// Frees every node that has been popped so far.
+    pub fn reclaim(&mut self) {
+        self.reclaimer_mut().reclaim()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use memory_order::AcqRelOrder;
+    use queue_tests::{check_sequential_fifo, check_concurrent_fifo, check_stalled_pusher, CountsDrops};
 
-    pub fn push(&self, item: T) {
-        let new_node = Box::into_raw(Box::new(Node::new()));
-        unsafe { (*new_node).data.get().write(MaybeUninit::new(item)) }
-        let mut tail: *mut Node<T>;
-        loop {
-            tail = self.tail.load(Ordering::SeqCst);
-
-            // grab the next pointer and make sure that tail has not changed under us
-            let next: *mut Node<T> = unsafe { (*tail).next.load(Ordering::SeqCst) };
-            if tail != self.tail.load(Ordering::SeqCst) {
-                continue
-            }
-
-            // if next pointer is not null, someone else pushed, so we should retry
-            if next != ptr::null_mut() {
-                continue
-            }
-
-            // if CAS succeeds on the tail, then we can commit our push
-            if unsafe { (*tail).next.compare_and_swap(ptr::null_mut(), new_node, Ordering::SeqCst) } == 
-                        ptr::null_mut() {
-                break
-            }
+    #[test]
+    fn drop_frees_items_left_in_the_queue() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let queue = DirtyQueue::<CountsDrops>::new();
+        for _ in 0..10 {
+            queue.push(CountsDrops(drops.clone()));
         }
-        // commit our push to the queue
-        self.tail.compare_and_swap(tail, new_node, Ordering::SeqCst);
+        drop(queue.pop());
+        drop(queue.pop());
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+        drop(queue);
+        assert_eq!(drops.load(Ordering::SeqCst), 10);
     }
 
-    pub fn pop(&self) -> Option<T> {
-        let mut head: *mut Node<T>;
-        let result: T;
-        loop {
-            head = self.head.load(Ordering::SeqCst);
-
-            let tail = self.tail.load(Ordering::SeqCst);
-            // grab the next pointer and make sure the head hasn't changed
-            let next = unsafe { (*head).next.load(Ordering::SeqCst) };
-
-            // if there are no more nodes, the queue is empty
-            if next == ptr::null_mut() {
-                return None
-            }
-
-            // someone beat us to popping
-            if head == tail {
-                continue
-            }
-            
-            // try to remove the next node
-            if self.head.compare_and_swap(head, next, Ordering::SeqCst) == head {
-                // since the CAS succeeded, we have exclusive access to next
-                result = unsafe { (*next).data.get().read().assume_init() };
-                break
-            }
+    #[test]
+    fn deferred_drop_frees_items_left_in_the_queue() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut queue = DeferredDirtyQueue::<CountsDrops>::new();
+        for _ in 0..10 {
+            queue.push(CountsDrops(drops.clone()));
         }
-        Some(result)
+        drop(queue.pop());
+        drop(queue.pop());
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+        // Reclaiming frees popped nodes, but items are not in them anymore.
+        queue.reclaim();
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+        drop(queue);
+        assert_eq!(drops.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn stalled_pusher_gets_helped() {
+        check_stalled_pusher(&DirtyQueue::<u64>::new());
+        check_stalled_pusher(&DirtyQueue::<u64, AcqRelOrder>::new());
+    }
+
+    #[test]
+    fn sequential_fifo() {
+        check_sequential_fifo(&DirtyQueue::<u64>::new(), 1000);
+        check_sequential_fifo(&DirtyQueue::<u64, AcqRelOrder>::new(), 1000);
+    }
+
+    #[test]
+    fn concurrent_no_loss_seq_cst() {
+        check_concurrent_fifo(&DirtyQueue::<u64>::new(), 4, 4, 20_000, 1);
     }
-}
 
-impl<T: Send + Sync> SyncQueue<T> for DirtyQueue<T> {
-    fn pop(&self) -> Option<T> {
-        self.pop()
+    #[test]
+    fn concurrent_no_loss_acq_rel() {
+        check_concurrent_fifo(&DirtyQueue::<u64, AcqRelOrder>::new(), 4, 4, 20_000, 1);
     }
 
-    fn push(&self, elem: T) {
-        self.push(elem)
+    #[test]
+    fn batches_keep_order() {
+        check_concurrent_fifo(&DirtyQueue::<u64>::new(), 4, 4, 20_000, 16);
+        check_concurrent_fifo(&DirtyQueue::<u64, AcqRelOrder>::new(), 4, 4, 20_000, 16);
     }
 }
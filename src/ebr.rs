@@ -0,0 +1,182 @@
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::cell::UnsafeCell;
+use reclaim::{Reclaimer, ReclaimGuard, Retired};
+use thread_id::{self, MAX_THREADS};
+
+/// Marks a thread that is not pinned.
+const INACTIVE: usize = usize::MAX;
+
+/// Tuning knobs for an EBR collector
+#[derive(Clone, Debug)]
+pub struct EbrConfig {
+    // How many pins a thread makes between attempts to advance the global epoch.
+    pub advance_every: usize,
+    // How much garbage a thread collects before trying to free it.
+    pub batch_size: usize,
+}
+
+impl Default for EbrConfig {
+    // These roughly match what crossbeam-epoch does internally.
+    fn default() -> EbrConfig {
+        EbrConfig { advance_every: 128, batch_size: 64 }
+    }
+}
+
+/// State only touched by the thread that owns a record
+struct LocalState {
+    // Number of live guards, so that nested pins do not unpin early.
+    guards: usize,
+    pins: usize,
+    // Retired nodes, tagged with the global epoch at the time they were retired.
+    limbo: Vec<(usize, Retired)>,
+    next_collect: usize,
+}
+
+/// A thread's pinned epoch, along with its garbage
+// Records sit on their own cachelines, since every pin writes to one.
+#[repr(align(64))]
+struct EbrRecord {
+    // The global epoch this thread saw when it pinned, or INACTIVE.
+    local: AtomicUsize,
+    state: UnsafeCell<LocalState>,
+}
+
+/// An epoch-based reclamation collector, based on Fraser's design
+// (Practical Lock-Freedom, 2004)
+// The global epoch only advances once every pinned thread has seen the current
+// one, so a node retired in epoch e is unreachable by the time it reaches e+2.
+// Unlike crossbeam-epoch, how often threads try to advance the epoch and how
+// much garbage they batch up are both configurable.
+// Records are indexed by thread id, and only the owning thread touches its state.
+pub struct EbrCollector {
+    global: AtomicUsize,
+    records: Box<[EbrRecord]>,
+    config: EbrConfig,
+}
+
+unsafe impl Send for EbrCollector {}
+unsafe impl Sync for EbrCollector {}
+
+impl EbrCollector {
+    pub fn new() -> EbrCollector {
+        EbrCollector::with_config(EbrConfig::default())
+    }
+
+    pub fn with_config(config: EbrConfig) -> EbrCollector {
+        assert!(config.advance_every > 0, "EBR must advance the epoch every so often");
+        let records: Vec<EbrRecord> = (0..MAX_THREADS).map(|_| EbrRecord {
+            local: AtomicUsize::new(INACTIVE),
+            state: UnsafeCell::new(LocalState {
+                guards: 0,
+                pins: 0,
+                limbo: Vec::new(),
+                next_collect: config.batch_size,
+            }),
+        }).collect();
+        EbrCollector {
+            global: AtomicUsize::new(0),
+            records: records.into_boxed_slice(),
+            config,
+        }
+    }
+
+    /// Pins the current thread, so that nothing it reads can be freed under it.
+    pub fn pin(&self) -> EbrGuard<'_> {
+        let record = &self.records[thread_id::current()];
+        let state = unsafe { &mut *record.state.get() };
+        state.guards += 1;
+        if state.guards == 1 {
+            record.local.store(self.global.load(Ordering::SeqCst), Ordering::SeqCst);
+            state.pins += 1;
+            if state.pins.is_multiple_of(self.config.advance_every) {
+                self.try_advance();
+            }
+        }
+        EbrGuard { collector: self, record }
+    }
+
+    /// Advances the global epoch, if every pinned thread has seen the current one.
+    fn try_advance(&self) {
+        let epoch = self.global.load(Ordering::SeqCst);
+        for record in self.records[..thread_id::high_water()].iter() {
+            let local = record.local.load(Ordering::SeqCst);
+            if local != INACTIVE && local != epoch {
+                return
+            }
+        }
+        let _ = self.global.compare_exchange(epoch, epoch + 1, Ordering::SeqCst, Ordering::Relaxed);
+    }
+
+    /// Frees everything in `state`'s limbo list that is two epochs old.
+    fn collect(&self, state: &mut LocalState) {
+        self.try_advance();
+        let epoch = self.global.load(Ordering::SeqCst);
+        let limbo = &mut state.limbo;
+        let mut i = 0;
+        while i < limbo.len() {
+            if limbo[i].0 + 2 <= epoch {
+                let (_, r) = limbo.swap_remove(i);
+                unsafe { r.free() };
+            } else {
+                i += 1;
+            }
+        }
+        state.next_collect = limbo.len() + self.config.batch_size;
+    }
+}
+
+impl Default for EbrCollector {
+    fn default() -> EbrCollector {
+        EbrCollector::new()
+    }
+}
+
+impl Drop for EbrCollector {
+    fn drop(&mut self) {
+        // No other threads can be pinned now, so everything can go.
+        for record in self.records.iter_mut() {
+            for (_, r) in record.state.get_mut().limbo.drain(..) {
+                unsafe { r.free() };
+            }
+        }
+    }
+}
+
+/// Keeps the current thread pinned until it is dropped
+pub struct EbrGuard<'a> {
+    collector: &'a EbrCollector,
+    record: &'a EbrRecord,
+}
+
+impl Reclaimer for EbrCollector {
+    type Guard<'a> = EbrGuard<'a>;
+
+    fn pin(&self) -> EbrGuard<'_> {
+        EbrCollector::pin(self)
+    }
+}
+
+// Retired nodes are dropped once the global epoch has advanced twice.
+impl<'a> ReclaimGuard for EbrGuard<'a> {
+    fn protect<T>(&self, _slot: usize, src: &AtomicPtr<T>) -> *mut T {
+        src.load(Ordering::SeqCst)
+    }
+
+    unsafe fn defer_free(&self, r: Retired) {
+        let state = &mut *self.record.state.get();
+        state.limbo.push((self.collector.global.load(Ordering::SeqCst), r));
+        if state.limbo.len() >= state.next_collect {
+            self.collector.collect(state);
+        }
+    }
+}
+
+impl<'a> Drop for EbrGuard<'a> {
+    fn drop(&mut self) {
+        let state = unsafe { &mut *self.record.state.get() };
+        state.guards -= 1;
+        if state.guards == 0 {
+            self.record.local.store(INACTIVE, Ordering::Release);
+        }
+    }
+}
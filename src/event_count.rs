@@ -0,0 +1,86 @@
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::sync::{Mutex, Condvar};
+use std::time::Instant;
+
+/// Lets threads sleep until some condition may have changed, without a lock on the fast path
+// This is an eventcount, as in Dmitry Vyukov's design
+// (http://www.1024cores.net/home/lock-free-algorithms/eventcounts)
+// A waiter registers with prepare_wait, checks its condition one last time,
+// and only then sleeps. A notifier changes the condition first, and then
+// checks for registered waiters, and only takes the lock when someone is asleep.
+// Both sides put a SeqCst fence between their own write and their read of the
+// other's, so one of the two always sees the other, and no wakeup is lost,
+// whatever ordering the condition was changed with.
+pub struct EventCount {
+    waiters: AtomicUsize,
+    // Bumped on every notification that found waiters, under the lock.
+    epoch: Mutex<usize>,
+    wakeup: Condvar,
+}
+
+/// A waiter's registration, from before it last checked its condition
+pub struct WaitKey(usize);
+
+impl EventCount {
+    pub fn new() -> EventCount {
+        EventCount {
+            waiters: AtomicUsize::new(0),
+            epoch: Mutex::new(0),
+            wakeup: Condvar::new(),
+        }
+    }
+
+    /// Registers the current thread as a waiter.
+    /// The caller has to check its condition again before calling `wait`.
+    pub fn prepare_wait(&self) -> WaitKey {
+        let epoch = *self.epoch.lock().unwrap();
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        // Orders the registration before the condition is checked again, whatever
+        // ordering that check uses, to pair with the notifier's fence.
+        atomic::fence(Ordering::SeqCst);
+        WaitKey(epoch)
+    }
+
+    /// Withdraws a registration, once the condition turned out to hold after all.
+    pub fn cancel_wait(&self, _key: WaitKey) {
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Sleeps until someone notifies after `key` was taken, or until `deadline` passes.
+    pub fn wait(&self, key: WaitKey, deadline: Option<Instant>) {
+        let mut epoch = self.epoch.lock().unwrap();
+        while *epoch == key.0 {
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break
+                    }
+                    epoch = self.wakeup.wait_timeout(epoch, deadline - now).unwrap().0;
+                },
+                None => epoch = self.wakeup.wait(epoch).unwrap(),
+            }
+        }
+        drop(epoch);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Wakes every waiter. Call this after making the condition hold.
+    pub fn notify_all(&self) {
+        // Orders the change to the condition before the check for waiters,
+        // to pair with the fence in prepare_wait.
+        atomic::fence(Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) == 0 {
+            return
+        }
+        let mut epoch = self.epoch.lock().unwrap();
+        *epoch = epoch.wrapping_add(1);
+        self.wakeup.notify_all();
+    }
+}
+
+impl Default for EventCount {
+    fn default() -> EventCount {
+        EventCount::new()
+    }
+}
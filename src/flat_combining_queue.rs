@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::collections::VecDeque;
+use std::cell::UnsafeCell;
+use std::thread;
+use sync_queue::SyncQueue;
+use thread_id::{self, MAX_THREADS};
+
+// States of a publication slot
+const IDLE: usize = 0;
+const PUSH: usize = 1;
+const POP: usize = 2;
+const DONE: usize = 3;
+
+/// A thread's entry in the publication list
+// Each slot sits on its own cacheline, so threads spinning on their own
+// request do not keep stealing the line from each other.
+#[repr(align(64))]
+pub struct Slot<T> {
+    // The pending request, which the combiner sets to DONE once it is applied.
+    pub op: AtomicUsize,
+    // The item to push, or the result of a pop.
+    pub item: UnsafeCell<Option<T>>,
+}
+
+/// MPMC Queue based on Hendler et al.'s flat combining design
+// (Flat Combining and the Synchronization-Parallelism Tradeoff, SPAA 2010)
+// Threads publish their requests in a per-thread slot. Whoever grabs the combiner
+// lock applies every published request to a plain VecDeque in one pass, so the
+// queue itself stays hot in a single core's cache, and the lock is taken once
+// per batch rather than once per operation.
+// Slots are indexed by thread id, which keeps the publication list dense.
+pub struct FlatCombiningQueue<T> {
+    combiner: AtomicBool,
+    slots: Box<[Slot<T>]>,
+    q: UnsafeCell<VecDeque<T>>,
+}
+
+unsafe impl<T: Send> Send for FlatCombiningQueue<T> {}
+unsafe impl<T: Send> Sync for FlatCombiningQueue<T> {}
+
+impl<T> FlatCombiningQueue<T> {
+    pub fn new() -> FlatCombiningQueue<T> {
+        let slots: Vec<Slot<T>> = (0..MAX_THREADS).map(|_| Slot {
+            op: AtomicUsize::new(IDLE),
+            item: UnsafeCell::new(None),
+        }).collect();
+        FlatCombiningQueue {
+            combiner: AtomicBool::new(false),
+            slots: slots.into_boxed_slice(),
+            q: UnsafeCell::new(VecDeque::new()),
+        }
+    }
+
+    pub fn push(&self, item: T) {
+        let result = self.apply(PUSH, Some(item));
+        debug_assert!(result.is_none());
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.apply(POP, None)
+    }
+
+    /// Publishes a request, then waits until some combiner has applied it.
+    fn apply(&self, op: usize, item: Option<T>) -> Option<T> {
+        let slot = &self.slots[thread_id::current()];
+        unsafe { *slot.item.get() = item };
+        slot.op.store(op, Ordering::Release);
+
+        loop {
+            if slot.op.load(Ordering::Acquire) == DONE {
+                slot.op.store(IDLE, Ordering::Relaxed);
+                return unsafe { (*slot.item.get()).take() }
+            }
+
+            // Nobody is combining, so we take over and serve everyone.
+            if !self.combiner.load(Ordering::Relaxed) &&
+                self.combiner.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok() {
+                self.combine();
+                self.combiner.store(false, Ordering::Release);
+            } else {
+                thread::yield_now();
+            }
+        }
+    }
+
+    /// Applies every published request. Only called while holding the combiner lock.
+    fn combine(&self) {
+        let q = unsafe { &mut *self.q.get() };
+        for slot in self.slots[..thread_id::high_water()].iter() {
+            match slot.op.load(Ordering::Acquire) {
+                PUSH => {
+                    let item = unsafe { (*slot.item.get()).take() };
+                    q.push_back(item.expect("push request without an item"));
+                },
+                POP => unsafe { *slot.item.get() = q.pop_front() },
+                _ => continue,
+            }
+            slot.op.store(DONE, Ordering::Release);
+        }
+    }
+}
+
+impl<T> Default for FlatCombiningQueue<T> {
+    fn default() -> FlatCombiningQueue<T> {
+        FlatCombiningQueue::new()
+    }
+}
+
+impl<T: Send + Sync> SyncQueue<T> for FlatCombiningQueue<T> {
+    fn pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.push(elem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use queue_tests::{check_sequential_fifo, check_concurrent_fifo};
+
+    #[test]
+    fn sequential_fifo() {
+        check_sequential_fifo(&FlatCombiningQueue::new(), 1000);
+    }
+
+    #[test]
+    fn concurrent_no_loss() {
+        check_concurrent_fifo(&FlatCombiningQueue::new(), 4, 4, 20_000, 1);
+    }
+
+    #[test]
+    fn more_threads_than_cores() {
+        // Combiners get preempted with requests still pending, and waiters have to yield to them.
+        // Half of the thread ids at most, since other tests are running too.
+        let cores = thread::available_parallelism().map_or(4, |n| n.get());
+        let per_side = (cores / 2 + 2).min(MAX_THREADS / 4);
+        check_concurrent_fifo(&FlatCombiningQueue::new(), per_side, per_side, 2_000, 1);
+    }
+}
@@ -0,0 +1,333 @@
+use std::sync::atomic::{AtomicPtr, AtomicBool, AtomicUsize, Ordering};
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::ptr;
+use reclaim::{Reclaimer, ReclaimGuard, Retired};
+
+/// Number of hazard pointers each thread may hold at once.
+// Two is enough for a Michael-Scott queue (head and head.next).
+pub const HAZARDS_PER_RECORD: usize = 2;
+
+/// Retired lists are scanned once they grow past this multiple of the
+/// total number of hazard pointers in the domain.
+const SCAN_FACTOR: usize = 2;
+
+/// Per-thread hazard pointer slots, along with that thread's retired nodes
+// Records are never freed while the domain is alive, so they can be linked
+// into a simple push-only list. A record is owned by whichever thread managed
+// to set its active flag, and only that thread touches its retired list.
+struct HazardRecord {
+    hazards: [AtomicPtr<u8>; HAZARDS_PER_RECORD],
+    active: AtomicBool,
+    // Whether a guard is using the record right now. Only its owner touches this.
+    in_use: Cell<bool>,
+    next: *mut HazardRecord,
+    retired: UnsafeCell<Vec<Retired>>,
+}
+
+/// The records of a domain
+// Threads keep the list alive while they have a record from it cached,
+// so they can still release the record after the domain is gone.
+struct RecordList {
+    head: AtomicPtr<HazardRecord>,
+    len: AtomicUsize,
+}
+
+impl RecordList {
+    /// Claims a record that another thread has released, or adds a new one.
+    fn claim(&self) -> &HazardRecord {
+        let mut curr = self.head.load(Ordering::SeqCst);
+        while let Some(record) = unsafe { curr.as_ref() } {
+            if !record.active.load(Ordering::Relaxed) &&
+                record.active.compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed).is_ok() {
+                return record
+            }
+            curr = record.next;
+        }
+
+        // Otherwise, allocate a new record and push it onto the list.
+        let record = Box::into_raw(Box::new(HazardRecord {
+            hazards: [AtomicPtr::new(ptr::null_mut()), AtomicPtr::new(ptr::null_mut())],
+            active: AtomicBool::new(true),
+            in_use: Cell::new(false),
+            next: ptr::null_mut(),
+            retired: UnsafeCell::new(Vec::new()),
+        }));
+        loop {
+            let head = self.head.load(Ordering::SeqCst);
+            unsafe { (*record).next = head; }
+            if self.head.compare_exchange(head, record, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                break
+            }
+        }
+        self.len.fetch_add(1, Ordering::SeqCst);
+        unsafe { &*record }
+    }
+}
+
+impl Drop for RecordList {
+    fn drop(&mut self) {
+        // The domain has already freed every retired node.
+        let mut curr = *self.head.get_mut();
+        while !curr.is_null() {
+            let record = unsafe { Box::from_raw(curr) };
+            curr = record.next;
+        }
+    }
+}
+
+/// A record that a thread keeps claimed between operations on a domain
+struct CachedRecord {
+    records: Arc<RecordList>,
+    record: *const HazardRecord,
+}
+
+impl Drop for CachedRecord {
+    fn drop(&mut self) {
+        // Hands the record over to other threads, once this one is done with the domain.
+        unsafe { (*self.record).active.store(false, Ordering::Release) };
+    }
+}
+
+// Gives out unique domain ids, since a new domain may reuse an old one's address.
+static NEXT_DOMAIN: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // This thread's record in every domain it has used, by domain id.
+    static RECORDS: RefCell<HashMap<usize, CachedRecord>> = RefCell::new(HashMap::new());
+}
+
+/// A hazard pointer domain, based on Maged Michael's 2004 design
+// Each data structure owns its own domain, so a scan only has to look at the
+// hazards of threads that have actually touched that structure.
+// Every thread claims a record the first time it uses a domain, and keeps it
+// until it exits, so operations do not have to search for a free one.
+pub struct HazardDomain {
+    id: usize,
+    records: Arc<RecordList>,
+}
+
+unsafe impl Send for HazardDomain {}
+unsafe impl Sync for HazardDomain {}
+
+impl HazardDomain {
+    pub fn new() -> HazardDomain {
+        HazardDomain {
+            id: NEXT_DOMAIN.fetch_add(1, Ordering::Relaxed),
+            records: Arc::new(RecordList {
+                head: AtomicPtr::new(ptr::null_mut()),
+                len: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Claims a hazard record for the current operation.
+    pub fn acquire(&self) -> HazardGuard<'_> {
+        let cached = RECORDS.try_with(|records| {
+            let mut records = records.borrow_mut();
+            if !records.contains_key(&self.id) {
+                // Let go of records in domains that have since been dropped.
+                records.retain(|_, cached| Arc::strong_count(&cached.records) > 1);
+                let record = self.records.claim();
+                records.insert(self.id, CachedRecord { records: self.records.clone(), record });
+            }
+            records[&self.id].record
+        });
+        match cached {
+            Ok(record) if unsafe { !(*record).in_use.get() } => {
+                HazardGuard::new(self, unsafe { &*record }, true)
+            },
+            // The cached record is busy with an outer operation, or the thread is exiting.
+            _ => HazardGuard::new(self, self.records.claim(), false),
+        }
+    }
+
+    /// Frees every retired node in `retired` that is not currently protected.
+    fn scan(&self, retired: &mut Vec<Retired>) {
+        // Snapshot all of the hazard pointers in the domain.
+        let mut hazards = vec![];
+        let mut curr = self.records.head.load(Ordering::SeqCst);
+        while let Some(record) = unsafe { curr.as_ref() } {
+            for hazard in record.hazards.iter() {
+                let p = hazard.load(Ordering::SeqCst);
+                if !p.is_null() {
+                    hazards.push(p);
+                }
+            }
+            curr = record.next;
+        }
+        hazards.sort();
+
+        // Anything that nobody has protected can be reclaimed.
+        let mut i = 0;
+        while i < retired.len() {
+            if hazards.binary_search(&retired[i].ptr()).is_ok() {
+                i += 1;
+            } else {
+                unsafe { retired.swap_remove(i).free() };
+            }
+        }
+    }
+}
+
+impl Default for HazardDomain {
+    fn default() -> HazardDomain {
+        HazardDomain::new()
+    }
+}
+
+impl Drop for HazardDomain {
+    fn drop(&mut self) {
+        // No other threads can hold hazards now, so every retired node can go.
+        // The records themselves go once no thread has one cached.
+        let mut curr = self.records.head.load(Ordering::SeqCst);
+        while let Some(record) = unsafe { curr.as_ref() } {
+            for r in unsafe { (*record.retired.get()).drain(..) } {
+                unsafe { r.free() };
+            }
+            curr = record.next;
+        }
+    }
+}
+
+/// A claimed hazard record, which is released when dropped
+pub struct HazardGuard<'a> {
+    domain: &'a HazardDomain,
+    record: &'a HazardRecord,
+    // Cached records stay claimed by their thread after the guard is gone.
+    cached: bool,
+}
+
+impl<'a> HazardGuard<'a> {
+    fn new(domain: &'a HazardDomain, record: &'a HazardRecord, cached: bool) -> HazardGuard<'a> {
+        record.in_use.set(true);
+        HazardGuard { domain, record, cached }
+    }
+
+    /// Loads `src` and protects the result in hazard slot `slot`.
+    // The pointer has to be re-read after publishing the hazard, otherwise it
+    // could have been retired and scanned in between.
+    pub fn protect<T>(&self, slot: usize, src: &AtomicPtr<T>) -> *mut T {
+        let mut p = src.load(Ordering::SeqCst);
+        loop {
+            self.record.hazards[slot].store(p as *mut u8, Ordering::SeqCst);
+            let q = src.load(Ordering::SeqCst);
+            if p == q {
+                return p
+            }
+            p = q;
+        }
+    }
+
+    /// Publishes `p` in hazard slot `slot` without validating it.
+    // The caller is responsible for re-checking that `p` is still reachable.
+    pub fn set<T>(&self, slot: usize, p: *mut T) {
+        self.record.hazards[slot].store(p as *mut u8, Ordering::SeqCst);
+    }
+
+    /// Clears hazard slot `slot`.
+    pub fn clear(&self, slot: usize) {
+        self.record.hazards[slot].store(ptr::null_mut(), Ordering::Release);
+    }
+}
+
+impl Reclaimer for HazardDomain {
+    type Guard<'a> = HazardGuard<'a>;
+
+    fn pin(&self) -> HazardGuard<'_> {
+        self.acquire()
+    }
+}
+
+// Retired nodes are dropped once no hazard pointer refers to them.
+impl<'a> ReclaimGuard for HazardGuard<'a> {
+    fn protect<T>(&self, slot: usize, src: &AtomicPtr<T>) -> *mut T {
+        HazardGuard::protect(self, slot, src)
+    }
+
+    fn set<T>(&self, slot: usize, p: *mut T) {
+        HazardGuard::set(self, slot, p)
+    }
+
+    unsafe fn defer_free(&self, r: Retired) {
+        let retired = &mut *self.record.retired.get();
+        retired.push(r);
+        let threshold = SCAN_FACTOR * HAZARDS_PER_RECORD * self.domain.records.len.load(Ordering::Relaxed);
+        if retired.len() >= threshold {
+            self.domain.scan(retired);
+        }
+    }
+}
+
+impl<'a> Drop for HazardGuard<'a> {
+    fn drop(&mut self) {
+        for hazard in self.record.hazards.iter() {
+            hazard.store(ptr::null_mut(), Ordering::Release);
+        }
+        self.record.in_use.set(false);
+        if !self.cached {
+            self.record.active.store(false, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use queue_tests::CountsDrops;
+
+    fn counted(drops: &Arc<AtomicUsize>) -> *mut CountsDrops {
+        Box::into_raw(Box::new(CountsDrops(drops.clone())))
+    }
+
+    #[test]
+    fn protected_node_outlives_scans() {
+        let domain = HazardDomain::new();
+        let protected_drops = Arc::new(AtomicUsize::new(0));
+        let other_drops = Arc::new(AtomicUsize::new(0));
+        let protected = counted(&protected_drops);
+        let src = AtomicPtr::new(protected);
+
+        let reader = domain.acquire();
+        assert_eq!(reader.protect(0, &src), protected);
+        // A second guard on the same thread gets a record of its own.
+        let writer = domain.acquire();
+        src.store(ptr::null_mut(), Ordering::SeqCst);
+        unsafe { writer.retire(protected) };
+        for _ in 0..100 {
+            unsafe { writer.retire(counted(&other_drops)) };
+        }
+        // Plenty of scans have run, and none of them freed the protected node.
+        assert!(other_drops.load(Ordering::SeqCst) > 0);
+        assert_eq!(protected_drops.load(Ordering::SeqCst), 0);
+
+        // Once the hazard is cleared, a scan can take it.
+        reader.clear(0);
+        for _ in 0..100 {
+            unsafe { writer.retire(counted(&other_drops)) };
+        }
+        assert_eq!(protected_drops.load(Ordering::SeqCst), 1);
+        drop(writer);
+        drop(reader);
+        drop(domain);
+        assert_eq!(other_drops.load(Ordering::SeqCst), 200);
+    }
+
+    #[test]
+    fn records_are_reused_across_threads() {
+        let domain = HazardDomain::new();
+        for _ in 0..3 {
+            thread::scope(|s| {
+                let threads: Vec<_> = (0..4).map(|_| s.spawn(|| drop(domain.acquire()))).collect();
+                // Joining waits for the threads' cached records to be released on exit.
+                for thread in threads {
+                    thread.join().unwrap();
+                }
+            });
+        }
+        // Exited threads hand their records back, so later ones don't need new ones.
+        assert!(domain.records.len.load(Ordering::SeqCst) <= 4);
+    }
+}
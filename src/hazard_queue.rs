@@ -0,0 +1,40 @@
+use hazard::HazardDomain;
+use ms_queue::MsQueue;
+
+/// Lockfree Michael-Scott queue whose nodes are reclaimed through hazard pointers
+// This follows Maged Michael's hazard pointer paper, which uses the
+// Michael-Scott queue as its running example. Every queue owns its own
+// domain, so the garbage it holds onto is bounded by its own threads.
+pub type HazardQueue<T> = MsQueue<T, HazardDomain>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use queue_tests::{check_sequential_fifo, check_concurrent_fifo, CountsDrops};
+
+    #[test]
+    fn sequential_fifo() {
+        check_sequential_fifo(&HazardQueue::new(), 1000);
+    }
+
+    #[test]
+    fn concurrent_no_loss() {
+        check_concurrent_fifo(&HazardQueue::new(), 4, 4, 20_000, 1);
+    }
+
+    #[test]
+    fn drop_frees_items_left_in_the_queue() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let queue = HazardQueue::new();
+        for _ in 0..10 {
+            queue.push(CountsDrops(drops.clone()));
+        }
+        drop(queue.pop());
+        drop(queue.pop());
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+        drop(queue);
+        assert_eq!(drops.load(Ordering::SeqCst), 10);
+    }
+}
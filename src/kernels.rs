@@ -1,10 +1,14 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use log::{trace, info};
-use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
 use std::thread;
 use std::fmt;
-use sync_queue::{SyncQueue, ImplType, create_impl};
-use std::time::Duration;
+use std::mem;
+use sync_queue::{SyncQueue, QueueHandle, ImplType, create_impl};
+use closable_queue::ClosableQueue;
+use sync_stack::create_stack_queue;
+use sync_channel::{ChannelSender, ChannelReceiver, Recv, channel_topology, create_channel_impl};
+use chase_lev::{self, Steal};
 
 // Used to indicate that a benchmark failed due to the queue implementation
 pub struct BenchmarkError {
@@ -24,16 +28,74 @@ pub enum WorkloadType {
     WriteHeavy,
     Mixed,
     MemoryHeavy,
+    ForkJoin,
+    Pairs,
 }
 
-pub fn run_workload(n_threads: usize, wt: &WorkloadType, it: &ImplType)
+/// Whether an implementation can run a given workload.
+// Channels can only run workloads with as many producers and consumers as they allow.
+// Per-worker deques only make sense for fork-join scheduling,
+// where each worker pushes to and pops from its own deque.
+pub fn is_supported(wt: &WorkloadType, it: &ImplType) -> bool {
+    if let Some(topology) = channel_topology(it) {
+        return match wt {
+            WorkloadType::ReadHeavy => topology.multi_consumer,
+            WorkloadType::WriteHeavy => topology.multi_producer,
+            WorkloadType::Mixed | WorkloadType::MemoryHeavy | WorkloadType::ForkJoin =>
+                topology.multi_producer && topology.multi_consumer,
+            WorkloadType::Pairs => true,
+        }
+    }
+
+    match it {
+        ImplType::ChaseLev => matches!(wt, WorkloadType::ForkJoin),
+        _ => true,
+    }
+}
+
+/// Knobs for how the kernels drive the queues
+#[derive(Clone, Debug)]
+pub struct KernelOptions {
+    // Run queue operations through per-thread handles, where the queue has them.
+    pub use_handles: bool,
+    // Push and pop this many items at a time.
+    pub batch_size: usize,
+}
+
+impl Default for KernelOptions {
+    fn default() -> KernelOptions {
+        KernelOptions { use_handles: false, batch_size: 1 }
+    }
+}
+
+pub fn run_workload(n_threads: usize, wt: &WorkloadType, it: &ImplType, opts: &KernelOptions)
     -> Result<i32, BenchmarkError> {
 
+    if let ImplType::ChaseLev = it {
+        return match wt {
+            WorkloadType::ForkJoin => fork_join_deques(n_threads),
+            _ => panic!("{:?} does not support the {:?} benchmark!", it, wt),
+        }
+    }
+
     match wt {
-        WorkloadType::ReadHeavy => read_heavy(Arc::new(create_impl::<u64>(it)), n_threads),
-        WorkloadType::WriteHeavy => write_heavy(Arc::new(create_impl::<u64>(it)), n_threads),
-        WorkloadType::Mixed => mixed(Arc::new(create_impl::<u64>(it)), n_threads),
-        WorkloadType::MemoryHeavy => memory_heavy(Arc::new(create_impl::<u64>(it)), n_threads),
+        WorkloadType::ReadHeavy => read_heavy(create_pool(it), it.capacity(), n_threads, opts),
+        WorkloadType::WriteHeavy => write_heavy(create_pool(it), it.capacity(), n_threads, opts),
+        WorkloadType::Mixed => mixed(create_pool(it), n_threads, opts),
+        WorkloadType::MemoryHeavy => memory_heavy(create_pool(it), n_threads, opts),
+        WorkloadType::ForkJoin => fork_join_shared(create_pool(it), n_threads, opts),
+        WorkloadType::Pairs => pairs(&|| create_pool(it), n_threads, opts),
+    }
+}
+
+/// Builds a pool over a queue, stack, or channel, given an ImplType.
+fn create_pool<T: 'static + Sync + Send>(it: &ImplType) -> Arc<dyn Pool<T>> {
+    if channel_topology(it).is_some() {
+        Arc::new(ChannelPool::new(create_channel_impl::<T>(it)))
+    } else if it.is_stack() {
+        Arc::new(ClosableQueue::new(create_stack_queue::<T>(it)))
+    } else {
+        Arc::new(ClosableQueue::new(create_impl::<T>(it)))
     }
 }
 
@@ -43,12 +105,12 @@ fn is_prime(num: u64) -> bool {
         false
     } else if num < 4 {
         true
-    } else if num % 2 == 0 {
+    } else if num.is_multiple_of(2) {
         false
     } else {
         let sqrt = (num as f64).sqrt() as u64;
         for i in (3..sqrt).step_by(2) {
-            if num % i == 0 {
+            if num.is_multiple_of(i) {
                 return false
             }
         }
@@ -56,47 +118,304 @@ fn is_prime(num: u64) -> bool {
     }
 }
 
+// The kernels below run on a Pool, so each is written once for queues, stacks, and
+// channels alike. Every thread works through its own endpoint, passes a quiescent state
+// between items, and goes offline once it is done or idle, so queues reclaimed through
+// QSBR can free memory. Threads can also run their operations through a handle, for
+// queues that have one, and push and pop items in batches. Consumers stop once the pool
+// is closed and drained, so no sentinel values are needed.
+
+/// Anything many threads can share items through
+trait Pool<T>: Send + Sync {
+    /// Returns an endpoint for the current thread to push and pop through.
+    fn endpoint(&self, opts: &KernelOptions) -> Box<dyn Endpoint<T> + '_>;
+
+    /// Lets poppers know that nothing more will be pushed.
+    /// Call this once every push has returned.
+    fn close(&self);
+}
+
+/// One thread's way into a pool
+trait Endpoint<T> {
+    fn push(&mut self, elem: T);
+
+    /// Pushes an item unless the pool is full, handing it back if it is.
+    fn try_push(&mut self, elem: T) -> Result<(), T> {
+        self.push(elem);
+        Ok(())
+    }
+
+    /// Pops an item, waiting for one while the pool is empty but still open.
+    /// Returns None once the pool is closed and drained.
+    fn pop(&mut self) -> Option<T>;
+
+    /// Called between items.
+    fn quiescent(&mut self) {}
+
+    /// Called once the thread is done or idle.
+    fn offline(&mut self) {}
+}
+
+// Queues and stacks are closed as ClosableQueues, and block while empty.
+impl<T: Send + Sync> Pool<T> for ClosableQueue<T> {
+    fn endpoint(&self, opts: &KernelOptions) -> Box<dyn Endpoint<T> + '_> {
+        Box::new(QueueOps::new(self, opts))
+    }
+
+    fn close(&self) {
+        ClosableQueue::close(self)
+    }
+}
+
+/// How often a thread refreshes its queue handle, in items.
+const HANDLE_REFRESH: usize = 128;
+
+/// Runs one thread's operations on a queue,
+/// through a handle if asked to and the queue has one
+// With a batch size above one, pushes are held back until a batch is full, and
+// pops are served from the last batch popped. Going offline flushes pushes, so
+// a thread has to go offline before anyone else can count on seeing its items.
+struct QueueOps<'a, T> {
+    queue: &'a ClosableQueue<T>,
+    handle: Option<Box<dyn QueueHandle<T> + 'a>>,
+    use_handles: bool,
+    items: usize,
+    batch_size: usize,
+    // Items waiting to be pushed.
+    outgoing: Vec<T>,
+    // Items popped, but not yet handed out, in reverse order.
+    incoming: Vec<T>,
+}
+
+impl<'a, T: Send + Sync> QueueOps<'a, T> {
+    fn new(queue: &'a ClosableQueue<T>, opts: &KernelOptions) -> QueueOps<'a, T> {
+        QueueOps {
+            queue,
+            handle: None,
+            use_handles: opts.use_handles,
+            items: 0,
+            batch_size: opts.batch_size,
+            outgoing: Vec::with_capacity(opts.batch_size),
+            incoming: Vec::with_capacity(opts.batch_size),
+        }
+    }
+
+    fn handle(&mut self) -> Option<&mut Box<dyn QueueHandle<T> + 'a>> {
+        if self.use_handles && self.handle.is_none() {
+            self.handle = self.queue.handle();
+            // Queues without handles just run each operation on its own.
+            self.use_handles = self.handle.is_some();
+        }
+        self.handle.as_mut()
+    }
+
+    /// Pops an item if there is one, without waiting.
+    fn try_pop(&mut self) -> Option<T> {
+        if self.batch_size > 1 {
+            if self.incoming.is_empty() {
+                let max = self.batch_size;
+                let mut batch = Vec::with_capacity(max);
+                match self.handle() {
+                    Some(handle) => handle.pop_batch(&mut batch, max),
+                    None => self.queue.pop_batch(&mut batch, max),
+                };
+                batch.reverse();
+                self.incoming = batch;
+            }
+            return self.incoming.pop()
+        }
+        match self.handle() {
+            Some(handle) => handle.pop(),
+            None => SyncQueue::pop(self.queue),
+        }
+    }
+
+    /// Pushes everything that is being held back.
+    fn flush(&mut self) {
+        if self.outgoing.is_empty() {
+            return
+        }
+        let mut outgoing = mem::take(&mut self.outgoing);
+        match self.handle() {
+            Some(handle) => handle.push_batch(&mut outgoing.drain(..)),
+            None => self.queue.push_batch(&mut outgoing.drain(..)),
+        }
+        self.outgoing = outgoing;
+    }
+}
+
+impl<'a, T: Send + Sync> Endpoint<T> for QueueOps<'a, T> {
+    fn push(&mut self, elem: T) {
+        if self.batch_size > 1 {
+            self.outgoing.push(elem);
+            if self.outgoing.len() >= self.batch_size {
+                self.flush();
+            }
+            return
+        }
+        match self.handle() {
+            Some(handle) => handle.push(elem),
+            None => SyncQueue::push(self.queue, elem),
+        }
+    }
+
+    // Skips the batching, since an item that is held back can't be handed back.
+    fn try_push(&mut self, elem: T) -> Result<(), T> {
+        match self.handle() {
+            Some(handle) => handle.try_push(elem),
+            None => SyncQueue::try_push(self.queue, elem),
+        }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        if let Some(item) = self.try_pop() {
+            return Some(item)
+        }
+        // No work to do ... wait for some, or for the queue to close
+        self.offline();
+        self.queue.pop_blocking().ok()
+    }
+
+    fn quiescent(&mut self) {
+        self.items += 1;
+        if let Some(handle) = self.handle.as_mut() {
+            if self.items.is_multiple_of(HANDLE_REFRESH) {
+                handle.refresh();
+            }
+        }
+        self.queue.quiescent();
+    }
+
+    fn offline(&mut self) {
+        self.flush();
+        self.handle = None;
+        self.queue.offline();
+    }
+}
+
+/// Shares a channel like a queue, by handing each thread its own sender and receiver
+// Threads clone the channel's ends the first time they push or pop. Channels that only
+// allow one sender or receiver hand over the original instead, which is_supported makes
+// sure only one thread asks for. Closing drops the original sender, and pops report
+// the channel closed once every sender has hung up, or it is closed and drained.
+struct ChannelPool<T> {
+    tx: Mutex<Option<Box<dyn ChannelSender<T>>>>,
+    rx: Mutex<Option<Box<dyn ChannelReceiver<T>>>>,
+    closed: AtomicBool,
+}
+
+impl<T: 'static + Send> ChannelPool<T> {
+    fn new(channel: (Box<dyn ChannelSender<T>>, Box<dyn ChannelReceiver<T>>)) -> ChannelPool<T> {
+        let (tx, rx) = channel;
+        ChannelPool { tx: Mutex::new(Some(tx)), rx: Mutex::new(Some(rx)), closed: AtomicBool::new(false) }
+    }
+
+    fn sender(&self) -> Box<dyn ChannelSender<T>> {
+        let mut tx = self.tx.lock().unwrap();
+        match tx.as_ref().and_then(|tx| tx.try_clone()) {
+            Some(tx) => tx,
+            None => tx.take().expect("Only one thread can send on a single-producer channel!"),
+        }
+    }
+
+    fn receiver(&self) -> Box<dyn ChannelReceiver<T>> {
+        let mut rx = self.rx.lock().unwrap();
+        match rx.as_ref().and_then(|rx| rx.try_clone()) {
+            Some(rx) => rx,
+            None => rx.take().expect("Only one thread can receive on a single-consumer channel!"),
+        }
+    }
+}
+
+impl<T: 'static + Send> Pool<T> for ChannelPool<T> {
+    fn endpoint(&self, _opts: &KernelOptions) -> Box<dyn Endpoint<T> + '_> {
+        Box::new(ChannelEndpoint { pool: self, tx: None, rx: None })
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.tx.lock().unwrap().take();
+    }
+}
+
+/// One thread's sender and receiver for a shared channel, taken when first needed
+struct ChannelEndpoint<'a, T> {
+    pool: &'a ChannelPool<T>,
+    tx: Option<Box<dyn ChannelSender<T>>>,
+    rx: Option<Box<dyn ChannelReceiver<T>>>,
+}
+
+impl<'a, T: 'static + Send> Endpoint<T> for ChannelEndpoint<'a, T> {
+    fn push(&mut self, elem: T) {
+        let pool = self.pool;
+        self.tx.get_or_insert_with(|| pool.sender()).send(elem)
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let pool = self.pool;
+        let rx = self.rx.get_or_insert_with(|| pool.receiver());
+        loop {
+            match rx.recv() {
+                Recv::Item(x) => return Some(x),
+                Recv::Empty if pool.closed.load(Ordering::SeqCst) => {
+                    // The last sends may have landed between the receive and the check.
+                    return match rx.recv() {
+                        Recv::Item(x) => Some(x),
+                        Recv::Empty | Recv::Disconnected => None,
+                    }
+                },
+                Recv::Empty => thread::yield_now(),
+                Recv::Disconnected => return None,
+            }
+        }
+    }
+}
+
 /// A single thread produces many integers,
 /// while many reader threads consume the values, and check primality.
-fn read_heavy(queue: Arc<Box<dyn SyncQueue<u64>>>, n_threads: usize) -> Result<i32, BenchmarkError> {
+// A pool bounded to `capacity` items can't hold all the work up front,
+// so the readers have to be running while it is pushed.
+fn read_heavy(pool: Arc<dyn Pool<u64>>, capacity: Option<usize>, n_threads: usize, opts: &KernelOptions)
+    -> Result<i32, BenchmarkError> {
     info!("Running read-heavy benchmark ...");
     // Benchmark constants
     let num_readers = n_threads;
     let num_ints = 2 << 20;
     let expected_primes = 155886;
+    let prefill = capacity.is_none_or(|capacity| capacity >= num_ints as usize);
+
+    let num_primes = Arc::new(AtomicI32::new(0));
+    let npcopy = num_primes.clone();
+    let count_primes = move |x| {
+        if is_prime(x) {
+            npcopy.fetch_add(1, Ordering::Relaxed);
+        }
+    };
+    let mut handles = vec![];
+    if !prefill {
+        trace!("Starting worker threads ...");
+        handles = consume(&pool, num_readers, opts, count_primes.clone());
+    }
 
-    // Initialize queue with work, including implicit exit messages
+    // Initialize the pool with work, then close it
     trace!("Pushing work to worker threads ...");
+    let mut ops = pool.endpoint(opts);
     for i in 0..num_ints {
-        queue.push(i);
+        ops.push(i);
     }
+    ops.offline();
+    drop(ops);
+    pool.close();
 
     // Start consumer threads
-    trace!("Starting worker threads ...");
-    let num_primes = Arc::new(AtomicI32::new(0));
-    let mut handles = vec![];
-    for _ in 0..num_readers {
-        let qcopy = queue.clone();
-        let npcopy = num_primes.clone();
-        let handle = thread::spawn(move ||{
-            loop {
-                match qcopy.pop() {
-                    Some(x) => {
-                        if is_prime(x) {
-                            npcopy.fetch_add(1, Ordering::Relaxed);
-                        }
-                    },
-                    // No work to do ... leave!
-                    None => break,
-                }
-            }
-        });
-        handles.push(handle);
+    if prefill {
+        trace!("Starting worker threads ...");
+        handles = consume(&pool, num_readers, opts, count_primes);
     }
 
     // Wait for all threads to return
     trace!("Waiting for worker threads to return ...");
-    while let Some(handle) = handles.pop() {
+    for handle in handles {
         handle.join().unwrap();
     }
 
@@ -108,39 +427,49 @@ fn read_heavy(queue: Arc<Box<dyn SyncQueue<u64>>>, n_threads: usize) -> Result<i
     }
 }
 
-/// Many worker threads search for primes and push to the queue if one is found.
-fn write_heavy(queue: Arc<Box<dyn SyncQueue<u64>>>, n_threads: usize) -> Result<i32, BenchmarkError> {
+/// Many worker threads search for primes and push to the pool if one is found.
+// A pool bounded to `capacity` items can't hold every prime,
+// so then it is drained while the writers are running instead of after.
+fn write_heavy(pool: Arc<dyn Pool<u64>>, capacity: Option<usize>, n_threads: usize, opts: &KernelOptions)
+    -> Result<i32, BenchmarkError> {
     info!("Running write-heavy benchmark ...");
     let num_writers = n_threads+1; // To distribute write contention, it's best if this is an odd prime.
     let num_ints = 2 << 20;
     let expected_primes = 155886;
+    let drain_after = capacity.is_none_or(|capacity| capacity >= expected_primes as usize);
 
     // Start all producer threads
     trace!("Starting worker threads ...");
-    let mut handles = vec![];
-    for tid in 0..num_writers {
-        let qcopy = queue.clone();
-        let handle = thread::spawn(move ||{
-            for i in (tid..num_ints).step_by(num_writers) {
-                if is_prime(i as u64) {
-                    qcopy.push(1);
-                }
+    let handles = produce(&pool, num_writers, opts, move |tid, push| {
+        for i in (tid..num_ints).step_by(num_writers) {
+            if is_prime(i as u64) {
+                push(1);
             }
-        });
-        handles.push(handle);
-    }
+        }
+    });
 
-    // Wait for all threads to return
+    let drained = Arc::new(AtomicI32::new(0));
+    let dcopy = drained.clone();
+    let drainer = if drain_after {
+        vec![]
+    } else {
+        consume(&pool, 1, opts, move |_| {
+            dcopy.fetch_add(1, Ordering::Relaxed);
+        })
+    };
+
+    // Wait for all threads to return, closing the pool once nothing more will be pushed
     trace!("Waiting for worker threads to return ...");
-    while let Some(handle) = handles.pop() {
-        handle.join().unwrap();
-    }
+    join_and_close(&*pool, handles, drainer);
 
     // Check that the produced values match the expected
-    let mut num_primes = 0;
-    while let Some(_) = queue.pop() {
+    let mut num_primes = drained.load(Ordering::SeqCst);
+    let mut ops = pool.endpoint(opts);
+    while ops.pop().is_some() {
         num_primes += 1;
+        ops.quiescent();
     }
+    ops.offline();
     if num_primes == expected_primes {
         Ok(num_primes)
     } else {
@@ -148,7 +477,7 @@ fn write_heavy(queue: Arc<Box<dyn SyncQueue<u64>>>, n_threads: usize) -> Result<
     }
 }
 
-fn mixed(queue: Arc<Box<dyn SyncQueue<u64>>>, n_threads: usize) -> Result<i32, BenchmarkError> {
+fn mixed(pool: Arc<dyn Pool<u64>>, n_threads: usize, opts: &KernelOptions) -> Result<i32, BenchmarkError> {
     info!("Running mixed benchmark ...");
     let num_readers = n_threads / 2;
     let num_writers = n_threads / 2;
@@ -157,44 +486,191 @@ fn mixed(queue: Arc<Box<dyn SyncQueue<u64>>>, n_threads: usize) -> Result<i32, B
 
     // Start all producer threads
     trace!("Starting worker threads ...");
+    let producers = produce(&pool, num_writers, opts, move |tid, push| {
+        for i in (tid..num_ints).step_by(num_writers) {
+            push(i as u64);
+        }
+    });
+
+    // Start consumer threads
+    trace!("Starting worker threads ...");
+    let num_primes = Arc::new(AtomicI32::new(0));
+    let npcopy = num_primes.clone();
+    let consumers = consume(&pool, num_readers, opts, move |x| {
+        if is_prime(x) {
+            npcopy.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+
+    // Wait for all threads to return, closing the pool once nothing more will be pushed
+    trace!("Waiting for worker threads to return ...");
+    join_and_close(&*pool, producers, consumers);
+
+    let result = num_primes.load(Ordering::SeqCst);
+    if result == expected_primes {
+        Ok(result)
+    } else {
+        Err(BenchmarkError { expected: expected_primes, actual: result })
+    }
+}
+
+fn memory_heavy(pool: Arc<dyn Pool<u64>>, n_threads: usize, opts: &KernelOptions) -> Result<i32, BenchmarkError> {
+    info!("Running memory-heavy benchmark ...");
+    let num_readers = n_threads / 2;
+    let num_writers = n_threads / 2;
+
+    // Benchmark constants
+    let num = 2 << 22;
+
+    trace!("Starting worker thread...");
+    let producers = produce(&pool, num_writers, opts, move |tid, push| {
+        for i in (tid..num).step_by(num_writers) {
+            push(i as u64);
+        }
+    });
+
+    trace!("Starting worker thread...");
+    let consumers = consume(&pool, num_readers, opts, |_| ());
+
+    // Wait for all threads to return, closing the pool once nothing more will be pushed
+    trace!("Waiting for worker threads to return ...");
+    join_and_close(&*pool, producers, consumers);
+    Ok(0)
+}
+
+/// Starts producer threads that each run `work`, numbered from zero,
+/// with a function to push items through.
+fn produce<T, F>(pool: &Arc<dyn Pool<T>>, n_threads: usize, opts: &KernelOptions, work: F)
+    -> Vec<thread::JoinHandle<()>>
+    where T: 'static, F: Fn(usize, &mut dyn FnMut(T)) + Clone + Send + 'static {
+    let mut handles = vec![];
+    for tid in 0..n_threads {
+        let pcopy = pool.clone();
+        let opts = opts.clone();
+        let work = work.clone();
+        let handle = thread::spawn(move ||{
+            let mut ops = pcopy.endpoint(&opts);
+            work(tid, &mut |x| {
+                ops.push(x);
+                ops.quiescent();
+            });
+            ops.offline();
+        });
+        handles.push(handle);
+    }
+    handles
+}
+
+/// Starts consumer threads that pop items and hand them to `work`, until the pool is closed and drained.
+fn consume<T, F>(pool: &Arc<dyn Pool<T>>, n_threads: usize, opts: &KernelOptions, work: F)
+    -> Vec<thread::JoinHandle<()>>
+    where T: 'static, F: Fn(T) + Clone + Send + 'static {
     let mut handles = vec![];
-    for tid in 0..num_writers {
-        let qcopy = queue.clone();
+    for _ in 0..n_threads {
+        let pcopy = pool.clone();
+        let opts = opts.clone();
+        let work = work.clone();
         let handle = thread::spawn(move ||{
-            for i in (tid..(num_ints+num_readers+1)).step_by(num_writers) {
-                qcopy.push(i as u64);
+            let mut ops = pcopy.endpoint(&opts);
+            while let Some(x) = ops.pop() {
+                work(x);
+                ops.quiescent();
             }
+            ops.offline();
         });
         handles.push(handle);
     }
+    handles
+}
+
+/// Waits for the producers, closes the pool, and then waits for the consumers.
+fn join_and_close<T>(pool: &dyn Pool<T>, producers: Vec<thread::JoinHandle<()>>,
+                     consumers: Vec<thread::JoinHandle<()>>) {
+    for handle in producers {
+        handle.join().unwrap();
+    }
+    pool.close();
+    for handle in consumers {
+        handle.join().unwrap();
+    }
+}
+
+// The fork-join kernels below count primes by recursively splitting a range of integers
+// into tasks, like a task scheduler would. Every task that is too large to run directly
+// forks two subtasks for its halves.
+
+/// A half-open range of integers to search for primes.
+type Task = (u64, u64);
+
+/// Ranges at most this large are searched directly instead of being split.
+const FORK_JOIN_GRAIN: u64 = 1 << 12;
+
+/// Runs a single task, handing any subtasks it forks to `fork`.
+/// `pending` counts the tasks that have been forked but not yet finished.
+/// Returns whether that was the last one.
+// A subtask that `fork` hands back is run right away instead. Forking never blocks,
+// so workers can't all wait on a full queue that only they could drain.
+fn run_task(task: Task, fork: &mut dyn FnMut(Task) -> Result<(), Task>,
+            pending: &AtomicUsize, num_primes: &AtomicI32) -> bool {
+    let (lo, hi) = task;
+    if hi - lo > FORK_JOIN_GRAIN {
+        let mid = lo + (hi - lo) / 2;
+        // Count the subtasks before publishing them, so pending never drops to zero early.
+        pending.fetch_add(2, Ordering::SeqCst);
+        for subtask in [(lo, mid), (mid, hi)] {
+            if let Err(subtask) = fork(subtask) {
+                // The parent is still pending, so this can't be the last task.
+                run_task(subtask, fork, pending, num_primes);
+            }
+        }
+    } else {
+        let count = (lo..hi).filter(|&i| is_prime(i)).count();
+        num_primes.fetch_add(count as i32, Ordering::Relaxed);
+    }
+    pending.fetch_sub(1, Ordering::SeqCst) == 1
+}
+
+/// All worker threads fork and run tasks through one shared pool.
+// Whoever finishes the last task closes the pool, which lets idle workers leave.
+fn fork_join_shared(pool: Arc<dyn Pool<Task>>, n_threads: usize, opts: &KernelOptions)
+    -> Result<i32, BenchmarkError> {
+    info!("Running shared fork-join benchmark ...");
+    let num_workers = n_threads;
+    let num_ints = 2 << 20;
+    let expected_primes = 155886;
+
+    // Seed the pool with a single task covering every integer
+    let pending = Arc::new(AtomicUsize::new(1));
+    let mut ops = pool.endpoint(opts);
+    ops.push((0, num_ints));
+    ops.offline();
+    drop(ops);
 
-    // Start consumer threads
     trace!("Starting worker threads ...");
     let num_primes = Arc::new(AtomicI32::new(0));
-    for _ in 0..num_readers {
-        let qcopy = queue.clone();
+    let mut handles = vec![];
+    for _ in 0..num_workers {
+        let pcopy = pool.clone();
+        let opts = opts.clone();
+        let pendcopy = pending.clone();
         let npcopy = num_primes.clone();
         let handle = thread::spawn(move ||{
-            loop {
-                match qcopy.pop() {
-                    Some(x) => {
-                        if x > num_ints as u64 {
-                            break
-                        } else if is_prime(x) {
-                            npcopy.fetch_add(1, Ordering::Relaxed);
-                        }
-                    },
-                    // No work to do ... sleep
-                    None => thread::sleep(Duration::from_millis(100)),
+            let mut ops = pcopy.endpoint(&opts);
+            // Keep going until every forked task has finished
+            while let Some(task) = ops.pop() {
+                if run_task(task, &mut |t| ops.try_push(t), &pendcopy, &npcopy) {
+                    pcopy.close();
                 }
+                ops.quiescent();
             }
+            ops.offline();
         });
         handles.push(handle);
     }
 
     // Wait for all threads to return
     trace!("Waiting for worker threads to return ...");
-    while let Some(handle) = handles.pop() {
+    for handle in handles {
         handle.join().unwrap();
     }
 
@@ -206,51 +682,136 @@ fn mixed(queue: Arc<Box<dyn SyncQueue<u64>>>, n_threads: usize) -> Result<i32, B
     }
 }
 
-fn memory_heavy(queue: Arc<Box<dyn SyncQueue<u64>>>, n_threads: usize)
-    -> Result<i32, BenchmarkError> {
-    info!("Running memory-heavy benchmark ...");
-    let num_readers = n_threads / 2;
-    let num_writers = n_threads / 2;
+/// Every worker thread forks tasks onto its own Chase-Lev deque,
+/// and steals from the others when it runs out of work.
+fn fork_join_deques(n_threads: usize) -> Result<i32, BenchmarkError> {
+    info!("Running work-stealing fork-join benchmark ...");
+    let num_workers = n_threads;
+    let num_ints = 2 << 20;
+    let expected_primes = 155886;
 
-    // Benchmark constants
-    let num = 2 << 22;
+    let mut workers = vec![];
+    let mut stealers = vec![];
+    for _ in 0..num_workers {
+        let (worker, stealer) = chase_lev::deque::<Task>();
+        workers.push(worker);
+        stealers.push(stealer);
+    }
+    let stealers = Arc::new(stealers);
 
-    trace!("Starting worker thread...");
+    // Seed the first worker's deque with a single task covering every integer
+    let pending = Arc::new(AtomicUsize::new(1));
+    workers[0].push((0, num_ints));
+
+    trace!("Starting worker threads ...");
+    let num_primes = Arc::new(AtomicI32::new(0));
     let mut handles = vec![];
-    for tid in 0..num_readers {
-        let qcopy = queue.clone();
+    for (tid, worker) in workers.into_iter().enumerate() {
+        let scopy = stealers.clone();
+        let pendcopy = pending.clone();
+        let npcopy = num_primes.clone();
         let handle = thread::spawn(move ||{
-            for i in (tid..(num+num_readers+1)).step_by(num_writers) {
-                qcopy.push(i as u64);
+            // Keep going until every forked task has finished
+            while pendcopy.load(Ordering::SeqCst) > 0 {
+                // Prefer our own work, then try everyone else in turn
+                let mut task = worker.pop();
+                for victim in (1..num_workers).map(|i| (tid + i) % num_workers) {
+                    if task.is_some() {
+                        break
+                    }
+                    if let Steal::Success(t) = scopy[victim].steal() {
+                        task = Some(t);
+                    }
+                }
+                match task {
+                    Some(task) => {
+                        run_task(task, &mut |t| { worker.push(t); Ok(()) }, &pendcopy, &npcopy);
+                    },
+                    None => thread::yield_now(),
+                }
             }
         });
         handles.push(handle);
     }
 
-    trace!("Starting worker thread...");
-    for _ in 0..num_writers {
-        let qcopy = queue.clone();
+    // Wait for all threads to return
+    trace!("Waiting for worker threads to return ...");
+    while let Some(handle) = handles.pop() {
+        handle.join().unwrap();
+    }
+
+    let result = num_primes.load(Ordering::SeqCst);
+    if result == expected_primes {
+        Ok(result)
+    } else {
+        Err(BenchmarkError { expected: expected_primes, actual: result })
+    }
+}
+
+// The pairs kernel below splits the threads into independent producer/consumer pairs,
+// each with its own pool. This is the only topology that single-producer,
+// single-consumer channels support.
+
+/// Each producer pushes its share of the integers to its own consumer, which checks primality.
+fn pairs(create: &dyn Fn() -> Arc<dyn Pool<u64>>, n_threads: usize, opts: &KernelOptions)
+    -> Result<i32, BenchmarkError> {
+    info!("Running pairs benchmark ...");
+    let num_pairs = n_threads / 2;
+    let num_ints = 2 << 20;
+    let expected_primes = 155886;
+
+    trace!("Starting worker threads ...");
+    let num_primes = Arc::new(AtomicI32::new(0));
+    let mut handles = vec![];
+    for tid in 0..num_pairs {
+        let pool = create();
+        // The producer is the pool's only one, so it can close the pool once it is done
+        let pcopy = pool.clone();
+        let opts_copy = opts.clone();
         let handle = thread::spawn(move ||{
-            loop {
-                match qcopy.pop() {
-                    Some(x) => {
-                        if x >= num as u64 {
-                            break
-                        }
-                    },
-                    // No work to do ... sleep
-                    None => thread::sleep(Duration::from_millis(100)),
-                }
+            let mut ops = pcopy.endpoint(&opts_copy);
+            for i in (tid..num_ints).step_by(num_pairs) {
+                ops.push(i as u64);
+                ops.quiescent();
             }
+            ops.offline();
+            drop(ops);
+            pcopy.close();
         });
         handles.push(handle);
+
+        let npcopy = num_primes.clone();
+        handles.extend(consume(&pool, 1, opts, move |x| {
+            if is_prime(x) {
+                npcopy.fetch_add(1, Ordering::Relaxed);
+            }
+        }));
     }
 
     // Wait for all threads to return
     trace!("Waiting for worker threads to return ...");
-    while let Some(handle) = handles.pop() {
+    for handle in handles {
         handle.join().unwrap();
     }
-    Ok(0)
+
+    let result = num_primes.load(Ordering::SeqCst);
+    if result == expected_primes {
+        Ok(result)
+    } else {
+        Err(BenchmarkError { expected: expected_primes, actual: result })
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fork_join_runs_on_small_bounded_queues() {
+        // Far fewer slots than the fork tree is wide, so most subtasks run inline.
+        for it in [ImplType::Bounded(4), ImplType::Arena(4), ImplType::CrossbeamArray(4)] {
+            let result = run_workload(4, &WorkloadType::ForkJoin, &it, &KernelOptions::default());
+            assert!(result.is_ok(), "{:?} failed the fork-join benchmark", it);
+        }
+    }
+}
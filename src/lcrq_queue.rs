@@ -0,0 +1,358 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::arch::asm;
+use std::marker::PhantomData;
+use crossbeam_epoch as epoch;
+use crossbeam_epoch::{Atomic, Owned};
+use sync_queue::SyncQueue;
+
+/// Number of cells in each ring.
+const RING_SIZE: u64 = 1 << 12;
+
+/// Number of failed attempts after which an enqueuer gives up on a ring and closes it.
+const STARVATION_LIMIT: usize = 1 << 10;
+
+// The top bit of a cell's first word marks it as safe to enqueue into,
+// and the top bit of a ring's tail marks the ring as closed.
+const SAFE_BIT: u64 = 1 << 63;
+const CLOSED_BIT: u64 = 1 << 63;
+
+/// An empty cell value. Values are boxed, so they are never null.
+const EMPTY: u64 = 0;
+
+/// A single cell in a ring
+// Both words are updated together with cmpxchg16b, which needs 16-byte alignment.
+#[repr(C, align(16))]
+pub struct Cell {
+    // The safe bit and the index of the enqueue/dequeue that may use this cell next.
+    pub safe_idx: AtomicU64,
+    // A raw Box<T> pointer, or EMPTY.
+    pub val: AtomicU64,
+}
+
+/// Double-width CAS on both words of a cell.
+// LLVM reserves rbx, so the low half of the new value is swapped in and out by hand.
+fn cas2(cell: &Cell, old: (u64, u64), new: (u64, u64)) -> bool {
+    let ok: u8;
+    unsafe {
+        asm!(
+            "xchg {tmp}, rbx",
+            "lock cmpxchg16b xmmword ptr [{dst}]",
+            "sete {ok}",
+            "mov rbx, {tmp}",
+            dst = in(reg) cell as *const Cell,
+            tmp = inout(reg) new.0 => _,
+            ok = out(reg_byte) ok,
+            inout("rax") old.0 => _,
+            inout("rdx") old.1 => _,
+            in("rcx") new.1,
+            options(nostack),
+        );
+    }
+    ok != 0
+}
+
+/// A closable concurrent ring queue (CRQ), the building block of an LCRQ
+// Enqueuers and dequeuers each claim an index with a single fetch-and-add,
+// then settle the cell at that index with one double-width CAS.
+// When a ring fills up or an enqueuer starves, the ring is closed for good,
+// and the outer queue moves on to a fresh ring.
+// Cells only hold a word, so every item is boxed on its way in. That is an
+// allocation per push, which the fetch-and-add design otherwise avoids, but it
+// lets the queue hold any T, like every other SyncQueue.
+pub struct Crq<T> {
+    head: AtomicU64,
+    tail: AtomicU64,
+    next: Atomic<Crq<T>>,
+    ring: Box<[Cell]>,
+    // The ring owns the boxed items behind its cell values.
+    _marker: PhantomData<Box<T>>,
+}
+
+impl<T> Crq<T> {
+    fn new() -> Crq<T> {
+        let ring: Vec<Cell> = (0..RING_SIZE).map(|i| Cell {
+            safe_idx: AtomicU64::new(SAFE_BIT | i),
+            val: AtomicU64::new(EMPTY),
+        }).collect();
+        Crq {
+            head: AtomicU64::new(0),
+            tail: AtomicU64::new(0),
+            next: Atomic::null(),
+            ring: ring.into_boxed_slice(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a ring that already holds `item`, so that it can be linked in one step.
+    fn with_item(item: u64) -> Crq<T> {
+        let crq = Crq::new();
+        crq.ring[0].safe_idx.store(SAFE_BIT, Ordering::Relaxed);
+        crq.ring[0].val.store(item, Ordering::Relaxed);
+        crq.tail.store(1, Ordering::Relaxed);
+        crq
+    }
+
+    /// Hands back the item a ring was created with, if it never got linked in.
+    fn take_item(&mut self) -> u64 {
+        let item = *self.ring[0].val.get_mut();
+        *self.ring[0].val.get_mut() = EMPTY;
+        item
+    }
+
+    /// Tries to enqueue `item`, returning false if the ring is closed.
+    fn enqueue(&self, item: u64) -> bool {
+        let mut tries = 0;
+        loop {
+            let t = self.tail.fetch_add(1, Ordering::SeqCst);
+            if t & CLOSED_BIT != 0 {
+                return false
+            }
+
+            let cell = &self.ring[(t % RING_SIZE) as usize];
+            let safe_idx = cell.safe_idx.load(Ordering::SeqCst);
+            let val = cell.val.load(Ordering::SeqCst);
+            let (safe, idx) = (safe_idx & SAFE_BIT, safe_idx & !SAFE_BIT);
+
+            // The cell is free for our index if no dequeuer has given up on it.
+            if val == EMPTY && idx <= t && (safe != 0 || self.head.load(Ordering::SeqCst) <= t) &&
+                cas2(cell, (safe_idx, EMPTY), (SAFE_BIT | t, item)) {
+                return true
+            }
+
+            // Close the ring if it is full, or if we keep losing to dequeuers.
+            tries += 1;
+            let h = self.head.load(Ordering::SeqCst);
+            if t.wrapping_sub(h) as i64 >= RING_SIZE as i64 || tries > STARVATION_LIMIT {
+                self.tail.fetch_or(CLOSED_BIT, Ordering::SeqCst);
+                return false
+            }
+        }
+    }
+
+    /// Tries to dequeue an item, returning EMPTY if the ring is empty.
+    fn dequeue(&self) -> u64 {
+        loop {
+            let h = self.head.fetch_add(1, Ordering::SeqCst);
+            let cell = &self.ring[(h % RING_SIZE) as usize];
+            loop {
+                let safe_idx = cell.safe_idx.load(Ordering::SeqCst);
+                let val = cell.val.load(Ordering::SeqCst);
+                let (safe, idx) = (safe_idx & SAFE_BIT, safe_idx & !SAFE_BIT);
+
+                // An enqueuer from a later lap already owns this cell.
+                if idx > h {
+                    break
+                }
+
+                if val != EMPTY {
+                    if idx == h {
+                        // Our item is here, so take it and free the cell for the next lap.
+                        if cas2(cell, (safe_idx, val), (safe | (h + RING_SIZE), EMPTY)) {
+                            return val
+                        }
+                    } else if cas2(cell, (safe_idx, val), (idx, val)) {
+                        // An item from an earlier lap is still here, so mark the cell
+                        // unsafe to stop enqueuers from our lap overwriting it.
+                        break
+                    }
+                } else if cas2(cell, (safe_idx, EMPTY), (safe | (h + RING_SIZE), EMPTY)) {
+                    // Nobody enqueued at our index yet, so push the cell on to the next lap.
+                    break
+                }
+            }
+
+            // We failed to dequeue at index h. If the ring is empty, stop here.
+            let t = self.tail.load(Ordering::SeqCst) & !CLOSED_BIT;
+            if t <= h + 1 {
+                self.fix_state();
+                return EMPTY
+            }
+        }
+    }
+
+    /// Pulls the tail back up to the head after dequeuers have overshot it.
+    fn fix_state(&self) {
+        loop {
+            let t = self.tail.load(Ordering::SeqCst);
+            let h = self.head.load(Ordering::SeqCst);
+            if self.tail.load(Ordering::SeqCst) != t {
+                continue
+            }
+            if h <= t {
+                return
+            }
+            if self.tail.compare_exchange(t, h, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return
+            }
+        }
+    }
+}
+
+impl<T> Drop for Crq<T> {
+    fn drop(&mut self) {
+        // Head and tail may have overshot each other, so check every cell for items left behind.
+        for cell in self.ring.iter_mut() {
+            let val = *cell.val.get_mut();
+            if val != EMPTY {
+                unsafe { drop(Box::from_raw(val as *mut T)) };
+            }
+        }
+    }
+}
+
+/// Lockfree queue based on Morrison and Afek's LCRQ design
+// (Fast Concurrent Queues for x86 Processors, PPoPP 2013)
+// This is a Michael-Scott style linked list of CRQ rings. Almost all operations
+// are served by fetch-and-add inside the current ring, so threads do not retry
+// the same CAS over and over under contention. Rings are reclaimed with crossbeam's
+// epoch collector, just like the nodes of EpochQueue.
+pub struct LcrqQueue<T> {
+    head: Atomic<Crq<T>>,
+    tail: Atomic<Crq<T>>,
+}
+
+impl<T> LcrqQueue<T> {
+    pub fn new() -> LcrqQueue<T> {
+        let queue = LcrqQueue {
+            head: Atomic::null(),
+            tail: Atomic::null(),
+        };
+
+        // Initialize the queue with one empty ring
+        unsafe {
+            let guard = epoch::unprotected();
+            let ring = Owned::new(Crq::new()).into_shared(guard);
+            queue.head.store(ring, Ordering::Relaxed);
+            queue.tail.store(ring, Ordering::Relaxed);
+        }
+        queue
+    }
+
+    pub fn push(&self, item: T) {
+        let item = Box::into_raw(Box::new(item)) as u64;
+        let guard = &epoch::pin(); // enter data structure
+        loop {
+            let shared_tail = self.tail.load(Ordering::SeqCst, guard);
+            let raw_tail = unsafe { shared_tail.deref() };
+
+            // The tail ring is lagging behind, so help move it forward.
+            let shared_next = raw_tail.next.load(Ordering::SeqCst, guard);
+            if !shared_next.is_null() {
+                let _ = self.tail.compare_and_set(shared_tail, shared_next, Ordering::SeqCst, guard);
+                continue
+            }
+
+            if raw_tail.enqueue(item) {
+                return
+            }
+
+            // The ring is closed, so link a new one that already holds our item.
+            let new_ring = Owned::new(Crq::with_item(item));
+            match raw_tail.next.compare_and_set(shared_next, new_ring, Ordering::SeqCst, guard) {
+                Ok(new_ring) => {
+                    let _ = self.tail.compare_and_set(shared_tail, new_ring, Ordering::SeqCst, guard);
+                    return
+                },
+                // Someone else linked a ring first, so throw ours away and retry.
+                Err(mut e) => {
+                    e.new.take_item();
+                    continue
+                },
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let guard = &epoch::pin(); // enter data structure
+        loop {
+            let shared_head = self.head.load(Ordering::SeqCst, guard);
+            let raw_head = unsafe { shared_head.deref() };
+
+            let val = raw_head.dequeue();
+            if val != EMPTY {
+                return Some(unsafe { *Box::from_raw(val as *mut T) })
+            }
+
+            // The head ring is empty. If there is no next ring, so is the queue.
+            let shared_next = raw_head.next.load(Ordering::SeqCst, guard);
+            if shared_next.is_null() {
+                return None
+            }
+
+            // Items may have landed in the head ring before it was closed, so check again.
+            let val = raw_head.dequeue();
+            if val != EMPTY {
+                return Some(unsafe { *Box::from_raw(val as *mut T) })
+            }
+
+            // Now the head ring is empty for good, so move on to the next one.
+            if self.head.compare_and_set(shared_head, shared_next, Ordering::SeqCst, guard).is_ok() {
+                unsafe { guard.defer_destroy(shared_head) };
+            }
+        }
+    }
+}
+
+impl<T> Default for LcrqQueue<T> {
+    fn default() -> LcrqQueue<T> {
+        LcrqQueue::new()
+    }
+}
+
+impl<T> Drop for LcrqQueue<T> {
+    fn drop(&mut self) {
+        // Each ring frees whatever items are still in it.
+        unsafe {
+            let guard = epoch::unprotected();
+            let mut ring = self.head.load(Ordering::SeqCst, guard);
+            while !ring.is_null() {
+                let next = ring.deref().next.load(Ordering::SeqCst, guard);
+                drop(ring.into_owned());
+                ring = next;
+            }
+        }
+    }
+}
+
+impl<T: Send + Sync> SyncQueue<T> for LcrqQueue<T> {
+    fn pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.push(elem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use queue_tests::{check_sequential_fifo, check_concurrent_fifo, CountsDrops};
+
+    #[test]
+    fn sequential_fifo_across_rings() {
+        check_sequential_fifo(&LcrqQueue::new(), 3 * RING_SIZE as usize);
+    }
+
+    #[test]
+    fn concurrent_no_loss() {
+        check_concurrent_fifo(&LcrqQueue::new(), 4, 4, 20_000, 1);
+    }
+
+    #[test]
+    fn drop_frees_items_left_in_rings() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let n = RING_SIZE as usize + 10;
+        let queue = LcrqQueue::new();
+        for _ in 0..n {
+            queue.push(CountsDrops(drops.clone()));
+        }
+        drop(queue.pop());
+        drop(queue.pop());
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+        drop(queue);
+        assert_eq!(drops.load(Ordering::SeqCst), n);
+    }
+}
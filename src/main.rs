@@ -11,7 +11,37 @@ pub mod kernels;
 pub mod sync_queue;
 pub mod dirty_queue;
 pub mod epoch_queue;
-use kernels::{WorkloadType};
+pub mod backoff;
+pub mod memory_order;
+pub mod padding;
+pub mod event_count;
+pub mod blocking_queue;
+pub mod closable_queue;
+pub mod hazard;
+pub mod hazard_queue;
+pub mod qsbr;
+pub mod ebr;
+pub mod reclaim;
+pub mod ms_queue;
+pub mod unrolled_queue;
+pub mod node_pool;
+pub mod bounded_queue;
+pub mod arena_queue;
+pub mod thread_id;
+pub mod two_lock_queue;
+pub mod flat_combining_queue;
+pub mod chase_lev;
+pub mod sync_channel;
+pub mod wait_free_queue;
+#[cfg(target_arch = "x86_64")]
+pub mod lcrq_queue;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+pub mod tagged_queue;
+pub mod sync_stack;
+pub mod treiber_stack;
+#[cfg(test)]
+mod queue_tests;
+use kernels::{WorkloadType, KernelOptions, is_supported};
 use benchmark::{run_benchmark};
 use log::{info};
 
@@ -33,17 +63,36 @@ fn main() {
         "write" => workloads.push(WorkloadType::WriteHeavy),
         "mixed" => workloads.push(WorkloadType::Mixed),
         "mem" => workloads.push(WorkloadType::MemoryHeavy),
-        "all" => workloads = vec![WorkloadType::ReadHeavy, WorkloadType::WriteHeavy, WorkloadType::Mixed, WorkloadType::MemoryHeavy],
+        "forkjoin" => workloads.push(WorkloadType::ForkJoin),
+        "pairs" => workloads.push(WorkloadType::Pairs),
+        "all" => workloads = vec![WorkloadType::ReadHeavy, WorkloadType::WriteHeavy, WorkloadType::Mixed, WorkloadType::MemoryHeavy,
+                                  WorkloadType::ForkJoin, WorkloadType::Pairs],
         _ => panic!("Invalid choice of benchmark!"),
     }
 
+    if opts.use_pool {
+        node_pool::enable();
+    }
+    let kernel_opts = KernelOptions {
+        use_handles: opts.use_handles,
+        batch_size: opts.batch_size,
+    };
+
     // Run each benchmark
     info!("Running benchmark(s) ...");
     for workload in &workloads {
-        let res = run_benchmark(opts.n_threads, &opts.impl_type, workload);
+        if !is_supported(workload, &opts.impl_type) {
+            println!("Skipped {:?}, which {:?} does not support.", &workload, &opts.impl_type);
+            continue
+        }
+        let res = run_benchmark(opts.n_threads, &opts.impl_type, workload, &kernel_opts);
         match res.result {
             Ok(_) => println!("Completed {:?} in {} ms.", &workload, res.duration.as_millis()),
             Err(e) => println!("Failed due to error: {}", e),
         }
+        let stats = node_pool::take_stats();
+        if node_pool::is_enabled() {
+            println!("Allocated {} new nodes, and reused {} from the pool.", stats.fresh, stats.reused);
+        }
     }
 }
@@ -0,0 +1,168 @@
+use std::sync::atomic::Ordering;
+
+/// The memory orderings a hand-written queue uses for its atomics
+// Queues take one of these as a type parameter, so the orderings are constants
+// and compile down to the same code as writing them out by hand.
+pub trait MemoryOrder: Send + Sync + 'static {
+    /// Loads of pointers that are about to be dereferenced.
+    const LOAD: Ordering;
+    /// Successful CASes, which both publish a node and take one over.
+    const RMW: Ordering;
+    /// Failed CASes, after which everything is loaded again anyway.
+    const RMW_FAIL: Ordering;
+}
+
+/// Sequentially consistent everything, which is what the queues started with
+pub struct SeqCstOrder;
+
+impl MemoryOrder for SeqCstOrder {
+    const LOAD: Ordering = Ordering::SeqCst;
+    const RMW: Ordering = Ordering::SeqCst;
+    const RMW_FAIL: Ordering = Ordering::SeqCst;
+}
+
+/// The weakest orderings the Michael-Scott algorithm is correct with
+// A pusher writes its item before the CAS that links the node in, and a popper
+// only reads the item after loading the link, so Release on the CAS and
+// Acquire on the load are enough to hand the item over. Nothing relies on a
+// single total order between different locations. The loom model below checks this.
+pub struct AcqRelOrder;
+
+impl MemoryOrder for AcqRelOrder {
+    const LOAD: Ordering = Ordering::Acquire;
+    const RMW: Ordering = Ordering::AcqRel;
+    const RMW_FAIL: Ordering = Ordering::Relaxed;
+}
+
+/// Which memory orderings a queue should be built with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OrderType {
+    #[default]
+    SeqCst,
+    AcqRel,
+}
+
+// Run with RUSTFLAGS="--cfg loom" cargo test --release loom
+#[cfg(all(test, loom))]
+mod loom_tests {
+    extern crate loom;
+
+    use super::*;
+    use std::marker::PhantomData;
+    use std::ptr;
+    use self::loom::cell::UnsafeCell;
+    use self::loom::sync::Arc;
+    use self::loom::sync::atomic::AtomicPtr;
+    use self::loom::thread;
+
+    struct Node {
+        data: UnsafeCell<u64>,
+        next: AtomicPtr<Node>,
+    }
+
+    impl Node {
+        fn alloc(data: u64) -> *mut Node {
+            Box::into_raw(Box::new(Node { data: UnsafeCell::new(data), next: AtomicPtr::new(ptr::null_mut()) }))
+        }
+    }
+
+    /// The push and pop steps MsQueue takes, on loom's atomics
+    // Loom cannot run the real queues, but it can check every interleaving of the same
+    // loads and CASes, with the same orderings, for data races on the items.
+    // Popped nodes are kept until the end, as the Leak reclaimer keeps them.
+    struct ModelQueue<O: MemoryOrder> {
+        head: AtomicPtr<Node>,
+        tail: AtomicPtr<Node>,
+        _order: PhantomData<O>,
+    }
+
+    impl<O: MemoryOrder> ModelQueue<O> {
+        fn new() -> ModelQueue<O> {
+            let sentinel = Node::alloc(0);
+            ModelQueue { head: AtomicPtr::new(sentinel), tail: AtomicPtr::new(sentinel), _order: PhantomData }
+        }
+
+        fn push(&self, item: u64) {
+            let node = Node::alloc(item);
+            loop {
+                let tail = self.tail.load(O::LOAD);
+                let next = unsafe { (*tail).next.load(O::LOAD) };
+                if tail != self.tail.load(O::LOAD) {
+                    thread::yield_now();
+                    continue
+                }
+                if !next.is_null() {
+                    let _ = self.tail.compare_exchange(tail, next, O::RMW, O::RMW_FAIL);
+                    thread::yield_now();
+                    continue
+                }
+                if unsafe { (*tail).next.compare_exchange(ptr::null_mut(), node, O::RMW, O::RMW_FAIL) }.is_ok() {
+                    let _ = self.tail.compare_exchange(tail, node, O::RMW, O::RMW_FAIL);
+                    return
+                }
+                thread::yield_now();
+            }
+        }
+
+        fn pop(&self) -> Option<u64> {
+            loop {
+                let head = self.head.load(O::LOAD);
+                let tail = self.tail.load(O::LOAD);
+                let next = unsafe { (*head).next.load(O::LOAD) };
+                if next.is_null() {
+                    return None
+                }
+                if head == tail {
+                    let _ = self.tail.compare_exchange(tail, next, O::RMW, O::RMW_FAIL);
+                    thread::yield_now();
+                    continue
+                }
+                if self.head.compare_exchange(head, next, O::RMW, O::RMW_FAIL).is_ok() {
+                    return Some(unsafe { (*next).data.with(|p| *p) })
+                }
+                thread::yield_now();
+            }
+        }
+
+        /// Frees every node, popped or not, once the model is done with the queue.
+        fn free_all(&self, first: *mut Node) {
+            let mut node = first;
+            while !node.is_null() {
+                let next = unsafe { (*node).next.load(O::LOAD) };
+                unsafe { drop(Box::from_raw(node)) };
+                node = next;
+            }
+        }
+    }
+
+    fn check_handover<O: MemoryOrder>() {
+        loom::model(|| {
+            let queue = Arc::new(ModelQueue::<O>::new());
+            let first = queue.head.load(O::LOAD);
+            let pushers: Vec<_> = [1, 2].iter().map(|&item| {
+                let queue = queue.clone();
+                thread::spawn(move || queue.push(item))
+            }).collect();
+            let mut popped: Vec<u64> = queue.pop().into_iter().collect();
+            for pusher in pushers {
+                pusher.join().unwrap();
+            }
+            while let Some(item) = queue.pop() {
+                popped.push(item);
+            }
+            popped.sort_unstable();
+            assert_eq!(popped, [1, 2]);
+            queue.free_all(first);
+        });
+    }
+
+    #[test]
+    fn loom_acq_rel_hands_items_over() {
+        check_handover::<AcqRelOrder>();
+    }
+
+    #[test]
+    fn loom_seq_cst_hands_items_over() {
+        check_handover::<SeqCstOrder>();
+    }
+}
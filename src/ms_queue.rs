@@ -0,0 +1,344 @@
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::ptr;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::cell::UnsafeCell;
+use reclaim::{Reclaimer, ReclaimGuard, Retired};
+use node_pool;
+use backoff::{Backoff, BackoffType};
+use memory_order::{MemoryOrder, SeqCstOrder};
+use padding::{Padding, Padded, NoPadding};
+use sync_queue::{SyncQueue, QueueHandle};
+
+/// Stores data and next pointers for items in the queue
+// With a padded layout, nodes are aligned to cachelines, to avoid false sharing
+// between cores. Padded nodes are too strictly aligned for the node pool, so
+// they always come from the global allocator.
+pub struct Node<T, P: Padding = NoPadding> {
+    // The UnsafeCell wrapper is required to move the T object out of a shared node.
+    // The MaybeUninit wrapper allows for uninitialized (sentinel) nodes.
+    pub data: UnsafeCell<MaybeUninit<T>>,
+    // This pointer to the next node is atomic to allow CAS.
+    pub next: AtomicPtr<Node<T, P>>,
+    _pad: P::Node,
+}
+
+impl<T, P: Padding> Node<T, P> {
+    fn new() -> Node<T, P> {
+        Node {
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+            next: AtomicPtr::new(ptr::null_mut()),
+            _pad: P::Node::default(),
+        }
+    }
+}
+
+/// Lockfree queue based on the Michael-Scott queue design,
+/// generic over how popped nodes are reclaimed
+// The algorithm is the same for every Reclaimer, so comparing instances of
+// this queue measures only the cost of reclamation. Every queue owns its own
+// reclaimer, although some (like Epoch) share global state underneath.
+// DirtyQueue, EpochQueue and HazardQueue are all this queue, under the Leak,
+// epoch and hazard pointer reclaimers.
+// Nodes come from the node pool, which falls back to the global allocator
+// unless it has been enabled.
+// Threads that lose a race back off according to `backoff` before retrying.
+// The memory orderings of the queue's own atomics come from `O`, while
+// protecting nodes is left to the reclaimer.
+// `P` decides whether nodes, and the head and tail, get their own cachelines.
+pub struct MsQueue<T, R: Reclaimer, O: MemoryOrder = SeqCstOrder, P: Padding = NoPadding> {
+    head: Padded<AtomicPtr<Node<T, P>>, P::Ends>,
+    tail: Padded<AtomicPtr<Node<T, P>>, P::Ends>,
+    reclaimer: R,
+    backoff: BackoffType,
+    _order: PhantomData<O>,
+}
+
+unsafe impl<T: Send, R: Reclaimer, O: MemoryOrder, P: Padding> Send for MsQueue<T, R, O, P> {}
+unsafe impl<T: Send, R: Reclaimer, O: MemoryOrder, P: Padding> Sync for MsQueue<T, R, O, P> {}
+
+impl<T, R: Reclaimer, O: MemoryOrder, P: Padding> MsQueue<T, R, O, P> {
+    pub fn new() -> MsQueue<T, R, O, P> {
+        MsQueue::with_reclaimer(R::default())
+    }
+
+    /// Creates a queue that uses a specific reclaimer,
+    /// for schemes with more than one way to configure them.
+    pub fn with_reclaimer(reclaimer: R) -> MsQueue<T, R, O, P> {
+        MsQueue::with_backoff(reclaimer, BackoffType::None)
+    }
+
+    pub fn with_backoff(reclaimer: R, backoff: BackoffType) -> MsQueue<T, R, O, P> {
+        // Initializes the queue with a sentinel node to simplify push/pop logic
+        let sentinel = node_pool::alloc(Node::new());
+        MsQueue {
+            head: Padded::new(AtomicPtr::new(sentinel)),
+            tail: Padded::new(AtomicPtr::new(sentinel)),
+            reclaimer,
+            backoff,
+            _order: PhantomData,
+        }
+    }
+
+    /// Returns the queue's reclaimer, which no other thread can be using.
+    pub fn reclaimer_mut(&mut self) -> &mut R {
+        &mut self.reclaimer
+    }
+
+    /// Returns a handle that runs many operations under one guard.
+    pub fn handle(&self) -> MsHandle<'_, T, R, O, P> {
+        MsHandle { queue: self, guard: Some(self.reclaimer.pin()) }
+    }
+
+    pub fn push(&self, item: T) {
+        let guard = self.reclaimer.pin();
+        self.push_in(&guard, item)
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let guard = self.reclaimer.pin();
+        self.pop_in(&guard)
+    }
+
+    /// Pushes every item in `items` with a single CAS.
+    pub fn push_batch<I: IntoIterator<Item = T>>(&self, items: I) {
+        let guard = self.reclaimer.pin();
+        self.push_batch_in(&guard, items)
+    }
+
+    /// Pops up to `max` items onto the end of `out` under one guard, and returns how many there were.
+    pub fn pop_batch(&self, out: &mut Vec<T>, max: usize) -> usize {
+        let guard = self.reclaimer.pin();
+        self.pop_batch_in(&guard, out, max)
+    }
+
+    fn new_node(item: T) -> *mut Node<T, P> {
+        let node = node_pool::alloc(Node::new());
+        unsafe { (*node).data.get().write(MaybeUninit::new(item)) }
+        node
+    }
+
+    fn push_in(&self, guard: &R::Guard<'_>, item: T) {
+        let node = MsQueue::<T, R, O, P>::new_node(item);
+        self.link_in(guard, node, node, || ())
+    }
+
+    /// Pushes `item`, running `linked` after its node is linked in but before the tail is swung to it.
+    // Tests use this to stall a pusher halfway through, so that they can check others get past it.
+    #[cfg(test)]
+    pub fn push_then<F: FnOnce()>(&self, item: T, linked: F) {
+        let guard = self.reclaimer.pin();
+        let node = MsQueue::<T, R, O, P>::new_node(item);
+        self.link_in(&guard, node, node, linked)
+    }
+
+    fn push_batch_in<I: IntoIterator<Item = T>>(&self, guard: &R::Guard<'_>, items: I) {
+        let mut items = items.into_iter();
+        let first = match items.next() {
+            Some(item) => MsQueue::<T, R, O, P>::new_node(item),
+            None => return,
+        };
+        // No one else can see the chain until it is linked in, so it can be built with plain stores.
+        let mut last = first;
+        for item in items {
+            let node = MsQueue::<T, R, O, P>::new_node(item);
+            unsafe { (*last).next.store(node, Ordering::Relaxed) };
+            last = node;
+        }
+        self.link_in(guard, first, last, || ())
+    }
+
+    /// Links the chain of nodes from `first` to `last` onto the end of the queue,
+    /// and runs `linked` once it is in, before swinging the tail.
+    fn link_in<F: FnOnce()>(&self, guard: &R::Guard<'_>, first: *mut Node<T, P>, last: *mut Node<T, P>,
+                           linked: F) {
+        let mut backoff = Backoff::new(self.backoff);
+        loop {
+            // The tail cannot be reclaimed while it is protected.
+            let tail = guard.protect(0, &self.tail);
+            let next = unsafe { (*tail).next.load(O::LOAD) };
+            if tail != self.tail.load(O::LOAD) {
+                backoff.spin();
+                continue
+            }
+
+            // The tail is lagging behind, so help move it forward.
+            if !next.is_null() {
+                let _ = self.tail.compare_exchange(tail, next, O::RMW, O::RMW_FAIL);
+                backoff.spin();
+                continue
+            }
+
+            // Try to link our chain after the tail.
+            if unsafe { (*tail).next.compare_exchange(ptr::null_mut(), first, O::RMW, O::RMW_FAIL) }
+                        .is_ok() {
+                linked();
+                let _ = self.tail.compare_exchange(tail, last, O::RMW, O::RMW_FAIL);
+                return
+            }
+            backoff.spin();
+        }
+    }
+
+    fn pop_batch_in(&self, guard: &R::Guard<'_>, out: &mut Vec<T>, max: usize) -> usize {
+        let mut popped = 0;
+        while popped < max {
+            match self.pop_in(guard) {
+                Some(item) => out.push(item),
+                None => break,
+            }
+            popped += 1;
+        }
+        popped
+    }
+
+    fn pop_in(&self, guard: &R::Guard<'_>) -> Option<T> {
+        let mut backoff = Backoff::new(self.backoff);
+        loop {
+            let head = guard.protect(0, &self.head);
+            let tail = self.tail.load(O::LOAD);
+            let next = unsafe { (*head).next.load(O::LOAD) };
+
+            // Protect next, then make sure head (and so next) is still reachable.
+            guard.set(1, next);
+            if head != self.head.load(O::LOAD) {
+                backoff.spin();
+                continue
+            }
+
+            // If there are no more nodes, the queue is empty
+            if next.is_null() {
+                return None
+            }
+
+            // The tail is lagging behind, so help move it forward first,
+            // so that it never points at a node we are about to retire.
+            if head == tail {
+                let _ = self.tail.compare_exchange(tail, next, O::RMW, O::RMW_FAIL);
+                backoff.spin();
+                continue
+            }
+
+            // Try to remove the next node
+            if self.head.compare_exchange(head, next, O::RMW, O::RMW_FAIL).is_ok() {
+                // Since the CAS succeeded, we have exclusive access to next's data,
+                // and the old head can be retired.
+                unsafe {
+                    let result = (*next).data.get().read().assume_init();
+                    guard.defer_free(Retired::pooled(head));
+                    return Some(result)
+                }
+            }
+            backoff.spin();
+        }
+    }
+}
+
+/// Runs many operations on an MsQueue under one guard
+// Holding a guard keeps the reclaimer from freeing anything it protects,
+// so long-running users should refresh it every so often.
+pub struct MsHandle<'a, T, R: Reclaimer + 'a, O: MemoryOrder, P: Padding> {
+    queue: &'a MsQueue<T, R, O, P>,
+    guard: Option<R::Guard<'a>>,
+}
+
+impl<'a, T, R: Reclaimer, O: MemoryOrder, P: Padding> MsHandle<'a, T, R, O, P> {
+    pub fn push(&mut self, item: T) {
+        self.queue.push_in(self.guard.as_ref().unwrap(), item)
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.queue.pop_in(self.guard.as_ref().unwrap())
+    }
+
+    pub fn push_batch<I: IntoIterator<Item = T>>(&mut self, items: I) {
+        self.queue.push_batch_in(self.guard.as_ref().unwrap(), items)
+    }
+
+    pub fn pop_batch(&mut self, out: &mut Vec<T>, max: usize) -> usize {
+        self.queue.pop_batch_in(self.guard.as_ref().unwrap(), out, max)
+    }
+
+    /// Drops the guard and takes a new one, so that the reclaimer can make progress.
+    pub fn refresh(&mut self) {
+        // The old guard has to go first, otherwise the new one would just nest inside it.
+        self.guard = None;
+        self.guard = Some(self.queue.reclaimer.pin());
+    }
+}
+
+impl<'a, T, R: Reclaimer, O: MemoryOrder, P: Padding> QueueHandle<T> for MsHandle<'a, T, R, O, P> {
+    fn push(&mut self, elem: T) {
+        self.push(elem)
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.pop()
+    }
+
+    fn push_batch(&mut self, items: &mut dyn Iterator<Item = T>) {
+        self.push_batch(items)
+    }
+
+    fn pop_batch(&mut self, out: &mut Vec<T>, max: usize) -> usize {
+        self.pop_batch(out, max)
+    }
+
+    fn refresh(&mut self) {
+        self.refresh()
+    }
+}
+
+impl<T, R: Reclaimer, O: MemoryOrder, P: Padding> Default for MsQueue<T, R, O, P> {
+    fn default() -> MsQueue<T, R, O, P> {
+        MsQueue::new()
+    }
+}
+
+impl<T, R: Reclaimer, O: MemoryOrder, P: Padding> Drop for MsQueue<T, R, O, P> {
+    fn drop(&mut self) {
+        // Everything still linked in is ours now, so it can go without the reclaimer.
+        // The head is the sentinel, whose data has already been taken.
+        let mut node = *self.head.get_mut();
+        unsafe {
+            let mut next = (*node).next.load(Ordering::Relaxed);
+            node_pool::free(node);
+            while !next.is_null() {
+                node = next;
+                next = (*node).next.load(Ordering::Relaxed);
+                (*node).data.get_mut().assume_init_drop();
+                node_pool::free(node);
+            }
+        }
+    }
+}
+
+impl<T: Send + Sync, R: Reclaimer, O: MemoryOrder, P: Padding> SyncQueue<T> for MsQueue<T, R, O, P> {
+    fn pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.push(elem)
+    }
+
+    fn push_batch(&self, items: &mut dyn Iterator<Item = T>) {
+        self.push_batch(items)
+    }
+
+    fn pop_batch(&self, out: &mut Vec<T>, max: usize) -> usize {
+        self.pop_batch(out, max)
+    }
+
+    fn quiescent(&self) {
+        self.reclaimer.quiescent()
+    }
+
+    fn offline(&self) {
+        self.reclaimer.offline()
+    }
+
+    fn handle(&self) -> Option<Box<dyn QueueHandle<T> + '_>> {
+        Some(Box::new(self.handle()))
+    }
+}
@@ -0,0 +1,167 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::cell::RefCell;
+use std::alloc::{self, Layout};
+use std::mem;
+use std::ptr;
+use crossbeam_queue::SegQueue;
+
+/// Blocks are pooled in 8-byte size classes up to this size.
+const MAX_BLOCK: usize = 256;
+const NUM_CLASSES: usize = MAX_BLOCK / 8 + 1;
+
+/// A thread keeps at most this many free blocks of each size class.
+const CACHE_LIMIT: usize = 256;
+
+/// Blocks move between a thread's cache and the shared overflow this many at a time.
+const BATCH_SIZE: usize = CACHE_LIMIT / 2;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static FRESH: AtomicUsize = AtomicUsize::new(0);
+static REUSED: AtomicUsize = AtomicUsize::new(0);
+
+/// Free blocks of one size class, on their way between threads
+struct Batch(Vec<*mut u8>);
+unsafe impl Send for Batch {}
+
+/// Batches that threads had no room for, one queue per size class
+static OVERFLOW: OnceLock<Vec<SegQueue<Batch>>> = OnceLock::new();
+
+fn overflow(class: usize) -> &'static SegQueue<Batch> {
+    &OVERFLOW.get_or_init(|| (0..NUM_CLASSES).map(|_| SegQueue::new()).collect())[class]
+}
+
+/// A thread's free blocks, along with how it got the blocks it handed out
+struct LocalCache {
+    classes: Vec<Vec<*mut u8>>,
+    fresh: usize,
+    reused: usize,
+}
+
+impl Drop for LocalCache {
+    fn drop(&mut self) {
+        // Hand everything to the other threads on the way out.
+        for (class, blocks) in self.classes.iter_mut().enumerate() {
+            if !blocks.is_empty() {
+                overflow(class).push(Batch(mem::take(blocks)));
+            }
+        }
+        FRESH.fetch_add(self.fresh, Ordering::Relaxed);
+        REUSED.fetch_add(self.reused, Ordering::Relaxed);
+    }
+}
+
+thread_local! {
+    static CACHE: RefCell<LocalCache> = RefCell::new(LocalCache {
+        classes: (0..NUM_CLASSES).map(|_| Vec::new()).collect(),
+        fresh: 0,
+        reused: 0,
+    });
+}
+
+/// Node allocation counts, while the pool is enabled
+pub struct AllocStats {
+    // Blocks that came from the global allocator.
+    pub fresh: usize,
+    // Blocks that were recycled from the pool.
+    pub reused: usize,
+}
+
+/// Turns the pool on for every queue that draws from it.
+/// Only call this before any of those queues exist.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns the allocation counts so far, and resets them.
+// Other threads only report their counts when they exit.
+pub fn take_stats() -> AllocStats {
+    let _ = CACHE.try_with(|cache| {
+        let mut cache = cache.borrow_mut();
+        FRESH.fetch_add(mem::take(&mut cache.fresh), Ordering::Relaxed);
+        REUSED.fetch_add(mem::take(&mut cache.reused), Ordering::Relaxed);
+    });
+    AllocStats {
+        fresh: FRESH.swap(0, Ordering::Relaxed),
+        reused: REUSED.swap(0, Ordering::Relaxed),
+    }
+}
+
+/// Which size class a type's blocks belong to, if it can be pooled
+// Every block in a class has the same layout, so a block can be reused for
+// any type of that size, and freed by whoever ends up with it.
+fn size_class<T>() -> Option<usize> {
+    let layout = Layout::new::<T>();
+    if layout.align() == 8 && layout.size() > 0 && layout.size() <= MAX_BLOCK {
+        Some(layout.size() / 8)
+    } else {
+        None
+    }
+}
+
+/// Moves `value` into a block from the pool, or a fresh allocation if the
+/// pool is disabled or empty.
+pub fn alloc<T>(value: T) -> *mut T {
+    let class = match size_class::<T>() {
+        Some(class) if is_enabled() => class,
+        _ => return Box::into_raw(Box::new(value)),
+    };
+
+    let block = CACHE.try_with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.classes[class].is_empty() {
+            if let Ok(Batch(blocks)) = overflow(class).pop() {
+                cache.classes[class] = blocks;
+            }
+        }
+        match cache.classes[class].pop() {
+            Some(block) => {
+                cache.reused += 1;
+                block
+            },
+            None => {
+                cache.fresh += 1;
+                ptr::null_mut()
+            },
+        }
+    }).unwrap_or(ptr::null_mut());
+
+    if block.is_null() {
+        return Box::into_raw(Box::new(value))
+    }
+    let p = block as *mut T;
+    unsafe { p.write(value) };
+    p
+}
+
+/// Drops the value at `p`, and gives its block back to the pool.
+///
+/// # Safety
+/// `p` must come from `alloc` or `Box::into_raw`, and must not be used again.
+pub unsafe fn free<T>(p: *mut T) {
+    let class = match size_class::<T>() {
+        Some(class) if is_enabled() => class,
+        _ => return drop(Box::from_raw(p)),
+    };
+
+    ptr::drop_in_place(p);
+    let block = p as *mut u8;
+    let cached = CACHE.try_with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let blocks = &mut cache.classes[class];
+        blocks.push(block);
+        if blocks.len() > CACHE_LIMIT {
+            let batch = blocks.split_off(blocks.len() - BATCH_SIZE);
+            overflow(class).push(Batch(batch));
+        }
+    });
+
+    // Our cache is already gone if this thread is exiting.
+    if cached.is_err() {
+        alloc::dealloc(block, Layout::new::<T>());
+    }
+}
@@ -0,0 +1,78 @@
+use std::ops::{Deref, DerefMut};
+
+/// Forces whatever holds it to start on its own cacheline, and to fill it
+// Being zero-sized, this adds no data, only alignment and the padding it implies.
+#[repr(align(64))]
+#[derive(Default)]
+pub struct CacheAligned;
+
+/// Which parts of a queue get padded out to cachelines
+// Queues take one of these as a type parameter and hold the associated types
+// as fields, so an unpadded layout costs nothing.
+pub trait Padding: Send + Sync + 'static {
+    /// Held by every node.
+    type Node: Default + Send + Sync;
+    /// Held by the queue's head and tail, each.
+    type Ends: Default + Send + Sync;
+}
+
+pub struct NoPadding;
+pub struct PadNodes;
+pub struct PadEnds;
+pub struct PadAll;
+
+impl Padding for NoPadding {
+    type Node = ();
+    type Ends = ();
+}
+
+impl Padding for PadNodes {
+    type Node = CacheAligned;
+    type Ends = ();
+}
+
+impl Padding for PadEnds {
+    type Node = ();
+    type Ends = CacheAligned;
+}
+
+impl Padding for PadAll {
+    type Node = CacheAligned;
+    type Ends = CacheAligned;
+}
+
+/// A value, padded out according to `A`
+pub struct Padded<T, A> {
+    value: T,
+    _align: A,
+}
+
+impl<T, A: Default> Padded<T, A> {
+    pub fn new(value: T) -> Padded<T, A> {
+        Padded { value, _align: A::default() }
+    }
+}
+
+impl<T, A> Deref for Padded<T, A> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T, A> DerefMut for Padded<T, A> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+/// Which padding layout a queue should be built with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PaddingType {
+    #[default]
+    None,
+    Nodes,
+    Ends,
+    All,
+}
@@ -0,0 +1,200 @@
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::cell::UnsafeCell;
+use reclaim::{Reclaimer, ReclaimGuard, Retired};
+use thread_id::{self, MAX_THREADS};
+
+/// Marks a thread that is not inside the data structure.
+const OFFLINE: usize = usize::MAX;
+
+/// Limbo lists are reclaimed each time they grow by this many nodes.
+const RECLAIM_THRESHOLD: usize = 64;
+
+/// Nodes a thread has retired, tagged with the global epoch at the time
+struct Limbo {
+    nodes: Vec<(usize, Retired)>,
+    // Reclaiming only after the list has grown again keeps a slow thread from
+    // making everyone else scan (and bump the epoch) on every operation.
+    next_reclaim: usize,
+}
+
+/// A thread's quiescent state, along with the nodes it has retired
+// Records sit on their own cachelines, since every operation writes to one.
+#[repr(align(64))]
+struct QsbrRecord {
+    // The global epoch this thread saw at its last quiescent state, or OFFLINE.
+    local: AtomicUsize,
+    limbo: UnsafeCell<Limbo>,
+}
+
+/// A quiescent-state-based reclamation domain
+// (McKenney and Slingwine, Read-Copy Update, 1998)
+// A node retired in epoch e can be freed once every thread has passed through
+// a quiescent state after e, since it can no longer hold a reference to it.
+// By default, every operation boundary counts as a quiescent state: a thread
+// comes online when it starts an operation, and goes offline when it finishes.
+// With explicit quiescent points, threads stay online between operations and
+// only announce quiescent states when told to, so an operation costs nothing
+// beyond reading the thread's own record.
+// Records are indexed by thread id, and only the owning thread touches its limbo list.
+pub struct QsbrDomain {
+    global: AtomicUsize,
+    records: Box<[QsbrRecord]>,
+    explicit: bool,
+}
+
+unsafe impl Send for QsbrDomain {}
+unsafe impl Sync for QsbrDomain {}
+
+impl QsbrDomain {
+    /// Creates a domain where every operation boundary is a quiescent state.
+    pub fn new() -> QsbrDomain {
+        QsbrDomain::with_mode(false)
+    }
+
+    /// Creates a domain where threads announce their own quiescent states,
+    /// through `quiescent` and `offline`.
+    pub fn with_quiescent_points() -> QsbrDomain {
+        QsbrDomain::with_mode(true)
+    }
+
+    fn with_mode(explicit: bool) -> QsbrDomain {
+        let records: Vec<QsbrRecord> = (0..MAX_THREADS).map(|_| QsbrRecord {
+            local: AtomicUsize::new(OFFLINE),
+            limbo: UnsafeCell::new(Limbo { nodes: Vec::new(), next_reclaim: RECLAIM_THRESHOLD }),
+        }).collect();
+        QsbrDomain {
+            global: AtomicUsize::new(0),
+            records: records.into_boxed_slice(),
+            explicit,
+        }
+    }
+
+    /// Brings the current thread online for an operation, if it is not already.
+    pub fn enter(&self) -> QsbrGuard<'_> {
+        let record = &self.records[thread_id::current()];
+        if !self.explicit || record.local.load(Ordering::Relaxed) == OFFLINE {
+            record.local.store(self.global.load(Ordering::SeqCst), Ordering::SeqCst);
+        }
+        QsbrGuard { domain: self, record }
+    }
+
+    /// Announces that the current thread holds no references into the data structure.
+    // Without explicit quiescent points, this already happens after every operation.
+    pub fn quiescent(&self) {
+        if !self.explicit {
+            return
+        }
+        let record = &self.records[thread_id::current()];
+        if record.local.load(Ordering::Relaxed) == OFFLINE {
+            return
+        }
+        let limbo = unsafe { &mut *record.limbo.get() };
+        if limbo.nodes.len() >= limbo.next_reclaim {
+            self.reclaim(record, limbo);
+        } else {
+            record.local.store(self.global.load(Ordering::SeqCst), Ordering::Release);
+        }
+    }
+
+    /// Takes the current thread offline until its next operation,
+    /// so that it does not hold up reclamation while it is idle or gone.
+    pub fn offline(&self) {
+        if self.explicit {
+            self.records[thread_id::current()].local.store(OFFLINE, Ordering::Release);
+        }
+    }
+
+    /// Frees every node in `limbo` that all online threads have moved past.
+    /// The current thread must be at a quiescent state.
+    fn reclaim(&self, record: &QsbrRecord, limbo: &mut Limbo) {
+        // Start a new epoch, so that threads passing a quiescent state from
+        // now on cannot reach anything retired so far.
+        let epoch = self.global.fetch_add(1, Ordering::SeqCst) + 1;
+        if record.local.load(Ordering::Relaxed) != OFFLINE {
+            // We are quiescent, so we can move straight into the new epoch.
+            record.local.store(epoch, Ordering::SeqCst);
+        }
+        let oldest = self.records[..thread_id::high_water()].iter()
+            .map(|r| r.local.load(Ordering::SeqCst))
+            .min()
+            .unwrap_or(OFFLINE);
+
+        let nodes = &mut limbo.nodes;
+        let mut i = 0;
+        while i < nodes.len() {
+            if nodes[i].0 < oldest {
+                let (_, r) = nodes.swap_remove(i);
+                unsafe { r.free() };
+            } else {
+                i += 1;
+            }
+        }
+        limbo.next_reclaim = nodes.len() + RECLAIM_THRESHOLD;
+    }
+}
+
+impl Default for QsbrDomain {
+    fn default() -> QsbrDomain {
+        QsbrDomain::new()
+    }
+}
+
+impl Drop for QsbrDomain {
+    fn drop(&mut self) {
+        // No other threads can be online now, so everything can go.
+        for record in self.records.iter_mut() {
+            for (_, r) in record.limbo.get_mut().nodes.drain(..) {
+                unsafe { r.free() };
+            }
+        }
+    }
+}
+
+/// An online thread's handle on the domain for one operation
+pub struct QsbrGuard<'a> {
+    domain: &'a QsbrDomain,
+    record: &'a QsbrRecord,
+}
+
+impl Reclaimer for QsbrDomain {
+    type Guard<'a> = QsbrGuard<'a>;
+
+    fn pin(&self) -> QsbrGuard<'_> {
+        self.enter()
+    }
+
+    fn quiescent(&self) {
+        QsbrDomain::quiescent(self)
+    }
+
+    fn offline(&self) {
+        QsbrDomain::offline(self)
+    }
+}
+
+// Retired nodes are dropped once every thread has passed a quiescent state.
+impl<'a> ReclaimGuard for QsbrGuard<'a> {
+    fn protect<T>(&self, _slot: usize, src: &AtomicPtr<T>) -> *mut T {
+        src.load(Ordering::SeqCst)
+    }
+
+    unsafe fn defer_free(&self, r: Retired) {
+        let limbo = &mut *self.record.limbo.get();
+        limbo.nodes.push((self.domain.global.load(Ordering::SeqCst), r));
+    }
+}
+
+impl<'a> Drop for QsbrGuard<'a> {
+    fn drop(&mut self) {
+        // With explicit quiescent points, the thread stays online until it says otherwise.
+        if self.domain.explicit {
+            return
+        }
+        // Otherwise, finishing an operation is a quiescent state.
+        self.record.local.store(OFFLINE, Ordering::SeqCst);
+        let limbo = unsafe { &mut *self.record.limbo.get() };
+        if limbo.nodes.len() >= limbo.next_reclaim {
+            self.domain.reclaim(self.record, limbo);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use sync_queue::SyncQueue;
+use sync_stack::SyncStack;
+use ms_queue::MsQueue;
+use reclaim::Reclaimer;
+use memory_order::MemoryOrder;
+use padding::Padding;
+
+/// An item that counts how many of its kind have been dropped
+pub struct CountsDrops(pub Arc<AtomicUsize>);
+
+impl Drop for CountsDrops {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+// Items are tagged with the producer that pushed them in the upper half,
+// and their position in that producer's sequence in the lower half.
+fn item(producer: usize, seq: usize) -> u64 {
+    ((producer as u64) << 32) | seq as u64
+}
+
+/// Pushes `n` items from one thread, and checks that they pop back out in order.
+pub fn check_sequential_fifo(queue: &dyn SyncQueue<u64>, n: usize) {
+    assert_eq!(queue.pop(), None);
+    for i in 0..n {
+        queue.push(i as u64);
+    }
+    for i in 0..n {
+        assert_eq!(queue.pop(), Some(i as u64));
+    }
+    assert_eq!(queue.pop(), None);
+}
+
+/// Runs `producers` threads, each pushing `per_producer` items, against `consumers`
+/// threads popping, `batch` items at a time on both sides, and checks that every item comes
+/// out exactly once, and that each consumer sees each producer's items in the order pushed.
+pub fn check_concurrent_fifo(queue: &dyn SyncQueue<u64>, producers: usize, consumers: usize,
+                             per_producer: usize, batch: usize) {
+    check_concurrent_fifo_after(queue, producers, consumers, per_producer, batch, &[])
+}
+
+/// Like `check_concurrent_fifo`, but `queued` is already in the queue, and has to come out as well.
+fn check_concurrent_fifo_after(queue: &dyn SyncQueue<u64>, producers: usize, consumers: usize,
+                               per_producer: usize, batch: usize, queued: &[u64]) {
+    let total = producers * per_producer + queued.len();
+    let popped = AtomicUsize::new(0);
+    let popped = &popped;
+    let mut seen: Vec<u64> = thread::scope(|s| {
+        for p in 0..producers {
+            s.spawn(move || {
+                if batch > 1 {
+                    for start in (0..per_producer).step_by(batch) {
+                        let end = per_producer.min(start + batch);
+                        queue.push_batch(&mut (start..end).map(|i| item(p, i)));
+                        queue.quiescent();
+                    }
+                } else {
+                    for i in 0..per_producer {
+                        queue.push(item(p, i));
+                        queue.quiescent();
+                    }
+                }
+                queue.offline();
+            });
+        }
+        let handles: Vec<_> = (0..consumers).map(|_| s.spawn(move || {
+            let mut mine = vec![];
+            let mut last: Vec<Option<u64>> = vec![None; producers + 1];
+            let mut out = Vec::with_capacity(batch);
+            while popped.load(Ordering::SeqCst) < total {
+                out.clear();
+                if batch > 1 {
+                    queue.pop_batch(&mut out, batch);
+                } else {
+                    out.extend(queue.pop());
+                }
+                queue.quiescent();
+                if out.is_empty() {
+                    thread::yield_now();
+                    continue
+                }
+                popped.fetch_add(out.len(), Ordering::SeqCst);
+                for &x in &out {
+                    let (p, seq) = ((x >> 32) as usize, x & 0xffff_ffff);
+                    assert!(last[p].is_none_or(|l| l < seq), "producer {}'s items came out of order", p);
+                    last[p] = Some(seq);
+                    mine.push(x);
+                }
+            }
+            queue.offline();
+            mine
+        })).collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(queue.pop(), None);
+
+    seen.sort_unstable();
+    let mut expected: Vec<u64> = (0..producers).flat_map(|p| (0..per_producer).map(move |i| item(p, i))).collect();
+    expected.extend_from_slice(queued);
+    expected.sort_unstable();
+    assert_eq!(seen, expected);
+}
+
+/// Pushes `n` items from one thread, and checks that they pop back out in reverse.
+pub fn check_sequential_lifo(stack: &dyn SyncStack<u64>, n: usize) {
+    assert_eq!(stack.pop(), None);
+    for i in 0..n {
+        stack.push(i as u64);
+    }
+    for i in (0..n).rev() {
+        assert_eq!(stack.pop(), Some(i as u64));
+    }
+    assert_eq!(stack.pop(), None);
+}
+
+/// Runs `producers` threads, each pushing `per_producer` items, against `consumers`
+/// threads popping, and checks that every item comes out exactly once.
+pub fn check_concurrent_stack(stack: &dyn SyncStack<u64>, producers: usize, consumers: usize,
+                              per_producer: usize) {
+    let total = producers * per_producer;
+    let popped = AtomicUsize::new(0);
+    let popped = &popped;
+    let mut seen: Vec<u64> = thread::scope(|s| {
+        for p in 0..producers {
+            s.spawn(move || {
+                for i in 0..per_producer {
+                    stack.push(item(p, i));
+                }
+            });
+        }
+        let handles: Vec<_> = (0..consumers).map(|_| s.spawn(move || {
+            let mut mine = vec![];
+            while popped.load(Ordering::SeqCst) < total {
+                match stack.pop() {
+                    Some(x) => {
+                        popped.fetch_add(1, Ordering::SeqCst);
+                        mine.push(x);
+                    },
+                    None => thread::yield_now(),
+                }
+            }
+            mine
+        })).collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(stack.pop(), None);
+
+    seen.sort_unstable();
+    let mut expected: Vec<u64> = (0..producers).flat_map(|p| (0..per_producer).map(move |i| item(p, i))).collect();
+    expected.sort_unstable();
+    assert_eq!(seen, expected);
+}
+
+/// Pushes `stalled` from a thread that stops right after linking its node in, and runs `f`
+/// while it is stopped there, with the tail lagging behind the last node.
+fn while_stalled<R, O, P, F>(queue: &MsQueue<u64, R, O, P>, stalled: u64, f: F)
+    where R: Reclaimer, O: MemoryOrder, P: Padding, F: FnOnce() {
+    let (linked_tx, linked_rx) = mpsc::channel();
+    let (resume_tx, resume_rx) = mpsc::channel::<()>();
+    thread::scope(|s| {
+        s.spawn(move || {
+            queue.push_then(stalled, || {
+                linked_tx.send(()).unwrap();
+                resume_rx.recv().unwrap();
+            });
+            SyncQueue::offline(queue);
+        });
+        linked_rx.recv().unwrap();
+        f();
+        resume_tx.send(()).unwrap();
+    });
+}
+
+/// Checks that pushes and pops get past a pusher that stalled before swinging the tail.
+pub fn check_stalled_pusher<R: Reclaimer, O: MemoryOrder, P: Padding>(stalled_queue: &MsQueue<u64, R, O, P>) {
+    let queue: &dyn SyncQueue<u64> = stalled_queue;
+    // Poppers have to swing the tail past the stalled node before they take it.
+    while_stalled(stalled_queue, 100, || {
+        assert_eq!(queue.pop(), Some(100));
+        assert_eq!(queue.pop(), None);
+    });
+    check_sequential_fifo(queue, 10);
+
+    // Pushers have to swing the tail before they can link in behind it.
+    while_stalled(stalled_queue, 200, || {
+        queue.push(201);
+        queue.push(202);
+        assert_eq!(queue.pop(), Some(200));
+        assert_eq!(queue.pop(), Some(201));
+        assert_eq!(queue.pop(), Some(202));
+    });
+    check_sequential_fifo(queue, 10);
+
+    // Nobody waits on the stalled pusher, however many threads are running.
+    let producers = 4;
+    let stalled = item(producers, 0);
+    while_stalled(stalled_queue, stalled, || {
+        check_concurrent_fifo_after(queue, producers, 4, 5_000, 1, &[stalled]);
+    });
+    check_sequential_fifo(queue, 10);
+}
@@ -0,0 +1,225 @@
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use crossbeam_epoch as epoch;
+use crossbeam_epoch::{Collector, LocalHandle, Shared};
+use node_pool;
+use ebr::EbrConfig;
+
+/// A memory reclamation scheme for lockfree data structures
+// Data structures hold one of these, and wrap every operation in a guard.
+// Nodes loaded through the guard stay valid until it is dropped, and nodes
+// retired through it are freed once no other guard can still reach them.
+pub trait Reclaimer: Send + Sync + Default {
+    type Guard<'a>: ReclaimGuard where Self: 'a;
+
+    /// Starts an operation on the data structure.
+    fn pin(&self) -> Self::Guard<'_>;
+
+    /// Announces that the current thread holds no references into the data structure.
+    /// Only schemes that rely on quiescent states need this.
+    fn quiescent(&self) {}
+
+    /// Announces that the current thread will not touch the data structure
+    /// until its next operation.
+    fn offline(&self) {}
+}
+
+/// Protects the nodes an operation touches, for as long as it is held
+pub trait ReclaimGuard {
+    /// Loads `src`, and keeps the result from being freed.
+    /// Schemes that protect individual pointers use `slot` to tell them apart.
+    fn protect<T>(&self, slot: usize, src: &AtomicPtr<T>) -> *mut T;
+
+    /// Keeps `p` from being freed, without checking that it is still reachable.
+    // The caller has to re-check reachability itself, e.g. by re-reading its source.
+    fn set<T>(&self, _slot: usize, _p: *mut T) {}
+
+    /// Retires a node that has been unlinked from the data structure,
+    /// to be freed through `r` once no other thread can reach it.
+    ///
+    /// # Safety
+    /// The node must be unreachable from the data structure,
+    /// and must not be retired twice.
+    unsafe fn defer_free(&self, r: Retired);
+
+    /// Retires a node that has been unlinked from the data structure.
+    ///
+    /// # Safety
+    /// `p` must come from `Box::into_raw`, be unreachable from the data
+    /// structure, and must not be retired twice.
+    unsafe fn retire<T>(&self, p: *mut T) {
+        self.defer_free(Retired::new(p))
+    }
+}
+
+/// Which reclamation scheme a generic data structure should use
+#[derive(Clone, Debug)]
+pub enum ReclaimType {
+    Leak,
+    Epoch,
+    OwnedEpoch,
+    Hazard,
+    Qsbr,
+    Ebr(EbrConfig),
+}
+
+/// A node that has been unlinked, along with how to free it
+pub struct Retired {
+    ptr: *mut u8,
+    deleter: unsafe fn(*mut u8),
+}
+
+unsafe fn drop_box<T>(ptr: *mut u8) {
+    drop(Box::from_raw(ptr as *mut T));
+}
+
+unsafe fn free_to_pool<T>(ptr: *mut u8) {
+    node_pool::free(ptr as *mut T);
+}
+
+impl Retired {
+    /// # Safety
+    /// `p` must come from `Box::into_raw`.
+    pub unsafe fn new<T>(p: *mut T) -> Retired {
+        Retired { ptr: p as *mut u8, deleter: drop_box::<T> }
+    }
+
+    /// # Safety
+    /// `p` must come from `node_pool::alloc`.
+    pub unsafe fn pooled<T>(p: *mut T) -> Retired {
+        Retired { ptr: p as *mut u8, deleter: free_to_pool::<T> }
+    }
+
+    pub fn ptr(&self) -> *mut u8 {
+        self.ptr
+    }
+
+    /// # Safety
+    /// No other thread may still be able to reach the node.
+    pub unsafe fn free(self) {
+        (self.deleter)(self.ptr)
+    }
+}
+
+/// Never frees retired nodes
+// This is what DirtyQueue does, and gives a lower bound on the cost of reclamation.
+#[derive(Default)]
+pub struct Leak;
+
+pub struct LeakGuard;
+
+impl Reclaimer for Leak {
+    type Guard<'a> = LeakGuard;
+
+    fn pin(&self) -> LeakGuard {
+        LeakGuard
+    }
+}
+
+impl ReclaimGuard for LeakGuard {
+    fn protect<T>(&self, _slot: usize, src: &AtomicPtr<T>) -> *mut T {
+        src.load(Ordering::SeqCst)
+    }
+
+    unsafe fn defer_free(&self, _r: Retired) {}
+}
+
+/// Defers frees to crossbeam-epoch's global collector
+#[derive(Default)]
+pub struct Epoch;
+
+impl Reclaimer for Epoch {
+    type Guard<'a> = epoch::Guard;
+
+    fn pin(&self) -> epoch::Guard {
+        epoch::pin()
+    }
+}
+
+impl ReclaimGuard for epoch::Guard {
+    fn protect<T>(&self, _slot: usize, src: &AtomicPtr<T>) -> *mut T {
+        src.load(Ordering::SeqCst)
+    }
+
+    unsafe fn defer_free(&self, r: Retired) {
+        self.defer_unchecked(move || r.free())
+    }
+
+    unsafe fn retire<T>(&self, p: *mut T) {
+        self.defer_destroy(Shared::from(p as *const T))
+    }
+}
+
+/// Defers frees to a crossbeam-epoch collector owned by the data structure
+// Garbage is no longer mixed with every other structure's, at the cost of
+// registering each thread with every collector it touches. Each thread keeps
+// its handles to itself, and drops them when it exits.
+pub struct OwnedEpoch {
+    id: usize,
+    collector: Collector,
+    // Lets threads tell when the collector is gone, so they can drop their handles to it.
+    alive: Arc<()>,
+}
+
+// Gives out unique collector ids, since a new collector may reuse an old one's address.
+static NEXT_COLLECTOR: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // This thread's handle to every owned collector it has used, by collector id.
+    static HANDLES: RefCell<HashMap<usize, (Weak<()>, LocalHandle)>> = RefCell::new(HashMap::new());
+}
+
+impl OwnedEpoch {
+    pub fn new() -> OwnedEpoch {
+        OwnedEpoch {
+            id: NEXT_COLLECTOR.fetch_add(1, Ordering::Relaxed),
+            collector: Collector::new(),
+            alive: Arc::new(()),
+        }
+    }
+}
+
+impl Default for OwnedEpoch {
+    fn default() -> OwnedEpoch {
+        OwnedEpoch::new()
+    }
+}
+
+impl Reclaimer for OwnedEpoch {
+    type Guard<'a> = epoch::Guard;
+
+    fn pin(&self) -> epoch::Guard {
+        let pinned = HANDLES.try_with(|handles| {
+            let mut handles = handles.borrow_mut();
+            if !handles.contains_key(&self.id) {
+                // Let go of handles to collectors that have since been dropped.
+                handles.retain(|_, (alive, _)| alive.strong_count() > 0);
+                handles.insert(self.id, (Arc::downgrade(&self.alive), self.collector.register()));
+            }
+            handles[&self.id].1.pin()
+        });
+        // While the thread is exiting, fall back to a handle just for this guard.
+        pinned.unwrap_or_else(|_| self.collector.register().pin())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ms_queue::MsQueue;
+    use queue_tests::check_concurrent_fifo;
+
+    #[test]
+    fn owned_epoch_across_short_lived_threads() {
+        // Every round starts fresh threads, which reuse the ids of the last round's.
+        let queue = MsQueue::<u64, OwnedEpoch>::new();
+        for _ in 0..3 {
+            check_concurrent_fifo(&queue, 4, 4, 5_000, 1);
+        }
+        drop(queue);
+        // Handles to the dropped collector get cleared out on the next registration.
+        check_concurrent_fifo(&MsQueue::<u64, OwnedEpoch>::new(), 2, 2, 5_000, 1);
+    }
+}
@@ -0,0 +1,216 @@
+use std::sync::mpsc::{self, TryRecvError};
+use lockfree::channel::{spsc, spmc, mpsc as lfmpsc, mpmc, RecvErr};
+use sync_queue::ImplType;
+
+/// The result of trying to receive from a channel
+pub enum Recv<T> {
+    Item(T),
+    // Nothing is in the channel right now, but senders are still connected.
+    Empty,
+    // Nothing is in the channel, and every sender has hung up.
+    Disconnected,
+}
+
+/// The producing end of a channel
+pub trait ChannelSender<T>: Send {
+    fn send(&mut self, elem: T);
+    /// Another sender for the same channel, if the channel allows several producers.
+    fn try_clone(&self) -> Option<Box<dyn ChannelSender<T>>>;
+}
+
+/// The consuming end of a channel
+pub trait ChannelReceiver<T>: Send {
+    fn recv(&mut self) -> Recv<T>;
+    /// Another receiver for the same channel, if the channel allows several consumers.
+    fn try_clone(&self) -> Option<Box<dyn ChannelReceiver<T>>>;
+}
+
+/// How many producers and consumers a channel allows.
+pub struct Topology {
+    pub multi_producer: bool,
+    pub multi_consumer: bool,
+}
+
+/// Returns the topology of a channel ImplType, or None if it is not a channel.
+pub fn channel_topology(t: &ImplType) -> Option<Topology> {
+    let (multi_producer, multi_consumer) = match t {
+        ImplType::StdMpsc => (true, false),
+        ImplType::LockfreeSpsc => (false, false),
+        ImplType::LockfreeSpmc => (false, true),
+        ImplType::LockfreeMpsc => (true, false),
+        ImplType::LockfreeMpmc => (true, true),
+        _ => return None,
+    };
+    Some(Topology { multi_producer, multi_consumer })
+}
+
+/// Constructor function for building channels given an ImplType.
+pub fn create_channel_impl<T: 'static + Send>(t: &ImplType)
+    -> (Box<dyn ChannelSender<T>>, Box<dyn ChannelReceiver<T>>) {
+    match t {
+        ImplType::StdMpsc => {
+            let (tx, rx) = mpsc::channel();
+            (Box::new(StdSender(tx)), Box::new(StdReceiver(rx)))
+        },
+        ImplType::LockfreeSpsc => {
+            let (tx, rx) = spsc::create();
+            (Box::new(SpscSender(tx)), Box::new(SpscReceiver(rx)))
+        },
+        ImplType::LockfreeSpmc => {
+            let (tx, rx) = spmc::create();
+            (Box::new(SpmcSender(tx)), Box::new(SpmcReceiver(rx)))
+        },
+        ImplType::LockfreeMpsc => {
+            let (tx, rx) = lfmpsc::create();
+            (Box::new(MpscSender(tx)), Box::new(MpscReceiver(rx)))
+        },
+        ImplType::LockfreeMpmc => {
+            let (tx, rx) = mpmc::create();
+            (Box::new(MpmcSender(tx)), Box::new(MpmcReceiver(rx)))
+        },
+        _ => panic!("{:?} is not a channel implementation!", t),
+    }
+}
+
+/// Converts the lockfree crate's receive result.
+fn from_lockfree<T>(res: Result<T, RecvErr>) -> Recv<T> {
+    match res {
+        Ok(elem) => Recv::Item(elem),
+        Err(RecvErr::NoMessage) => Recv::Empty,
+        Err(RecvErr::NoSender) => Recv::Disconnected,
+    }
+}
+
+/// MPSC channel from the standard library
+struct StdSender<T>(mpsc::Sender<T>);
+struct StdReceiver<T>(mpsc::Receiver<T>);
+
+impl<T: 'static + Send> ChannelSender<T> for StdSender<T> {
+    fn send(&mut self, elem: T) {
+        if self.0.send(elem).is_err() {
+            panic!("Every receiver hung up!");
+        }
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ChannelSender<T>>> {
+        Some(Box::new(StdSender(self.0.clone())))
+    }
+}
+
+impl<T: 'static + Send> ChannelReceiver<T> for StdReceiver<T> {
+    fn recv(&mut self) -> Recv<T> {
+        match self.0.try_recv() {
+            Ok(elem) => Recv::Item(elem),
+            Err(TryRecvError::Empty) => Recv::Empty,
+            Err(TryRecvError::Disconnected) => Recv::Disconnected,
+        }
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ChannelReceiver<T>>> {
+        None
+    }
+}
+
+/// SPSC channel from the lockfree crate
+struct SpscSender<T>(spsc::Sender<T>);
+struct SpscReceiver<T>(spsc::Receiver<T>);
+
+impl<T: 'static + Send> ChannelSender<T> for SpscSender<T> {
+    fn send(&mut self, elem: T) {
+        if self.0.send(elem).is_err() {
+            panic!("Every receiver hung up!");
+        }
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ChannelSender<T>>> {
+        None
+    }
+}
+
+impl<T: 'static + Send> ChannelReceiver<T> for SpscReceiver<T> {
+    fn recv(&mut self) -> Recv<T> {
+        from_lockfree(self.0.recv())
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ChannelReceiver<T>>> {
+        None
+    }
+}
+
+/// SPMC channel from the lockfree crate
+struct SpmcSender<T>(spmc::Sender<T>);
+struct SpmcReceiver<T>(spmc::Receiver<T>);
+
+impl<T: 'static + Send> ChannelSender<T> for SpmcSender<T> {
+    fn send(&mut self, elem: T) {
+        if self.0.send(elem).is_err() {
+            panic!("Every receiver hung up!");
+        }
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ChannelSender<T>>> {
+        None
+    }
+}
+
+impl<T: 'static + Send> ChannelReceiver<T> for SpmcReceiver<T> {
+    fn recv(&mut self) -> Recv<T> {
+        from_lockfree(self.0.recv())
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ChannelReceiver<T>>> {
+        Some(Box::new(SpmcReceiver(self.0.clone())))
+    }
+}
+
+/// MPSC channel from the lockfree crate
+struct MpscSender<T>(lfmpsc::Sender<T>);
+struct MpscReceiver<T>(lfmpsc::Receiver<T>);
+
+impl<T: 'static + Send> ChannelSender<T> for MpscSender<T> {
+    fn send(&mut self, elem: T) {
+        if self.0.send(elem).is_err() {
+            panic!("Every receiver hung up!");
+        }
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ChannelSender<T>>> {
+        Some(Box::new(MpscSender(self.0.clone())))
+    }
+}
+
+impl<T: 'static + Send> ChannelReceiver<T> for MpscReceiver<T> {
+    fn recv(&mut self) -> Recv<T> {
+        from_lockfree(self.0.recv())
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ChannelReceiver<T>>> {
+        None
+    }
+}
+
+/// MPMC channel from the lockfree crate
+struct MpmcSender<T>(mpmc::Sender<T>);
+struct MpmcReceiver<T>(mpmc::Receiver<T>);
+
+impl<T: 'static + Send> ChannelSender<T> for MpmcSender<T> {
+    fn send(&mut self, elem: T) {
+        if self.0.send(elem).is_err() {
+            panic!("Every receiver hung up!");
+        }
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ChannelSender<T>>> {
+        Some(Box::new(MpmcSender(self.0.clone())))
+    }
+}
+
+impl<T: 'static + Send> ChannelReceiver<T> for MpmcReceiver<T> {
+    fn recv(&mut self) -> Recv<T> {
+        from_lockfree(self.0.recv())
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn ChannelReceiver<T>>> {
+        Some(Box::new(MpmcReceiver(self.0.clone())))
+    }
+}
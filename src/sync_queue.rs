@@ -1,24 +1,158 @@
 use std::collections::VecDeque;
 use std::sync::Mutex;
 use spin::Mutex as Spinlock;
-use crossbeam_queue::SegQueue;
+use std::thread;
+use crossbeam_queue::{SegQueue, ArrayQueue};
 use lockfree::queue::Queue as LFQueue;
-use dirty_queue::DirtyQueue;
+use dirty_queue::{DirtyQueue, DeferredDirtyQueue, DirtyReclaim, Deferred};
 use epoch_queue::EpochQueue;
+use hazard_queue::HazardQueue;
+use hazard::HazardDomain;
+use qsbr::QsbrDomain;
+use ebr::EbrCollector;
+use reclaim::{ReclaimType, Leak, Epoch, OwnedEpoch};
+use backoff::BackoffType;
+use memory_order::{OrderType, MemoryOrder, SeqCstOrder, AcqRelOrder};
+use padding::{PaddingType, Padding, NoPadding, PadNodes, PadEnds, PadAll};
+use ms_queue::MsQueue;
+use unrolled_queue::UnrolledQueue;
+use bounded_queue::BoundedQueue;
+use arena_queue::ArenaQueue;
+use wait_free_queue::WaitFreeQueue;
+use two_lock_queue::{TwoLockMutexQueue, TwoLockSpinQueue};
+use flat_combining_queue::FlatCombiningQueue;
+#[cfg(target_arch = "x86_64")]
+use lcrq_queue::LcrqQueue;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+use tagged_queue::TaggedQueue;
 
 pub trait SyncQueue<T>: Send + Sync {
     fn pop(&self) -> Option<T>;
-    fn push(&self, T) -> ();
+    fn push(&self, elem: T);
+
+    /// Pushes `elem` if there is room for it, otherwise hands it back.
+    /// Unbounded queues always have room.
+    fn try_push(&self, elem: T) -> Result<(), T> {
+        self.push(elem);
+        Ok(())
+    }
+
+    /// Pushes every item in `items`, in order.
+    // This takes an iterator object rather than anything IntoIterator, so that
+    // SyncQueue can still be used as a trait object.
+    fn push_batch(&self, items: &mut dyn Iterator<Item = T>) {
+        for item in items {
+            self.push(item);
+        }
+    }
+
+    /// Pops up to `max` items onto the end of `out`, and returns how many there were.
+    fn pop_batch(&self, out: &mut Vec<T>, max: usize) -> usize {
+        pop_each(|| self.pop(), out, max)
+    }
+
+    /// Announces that the current thread holds no references into the queue.
+    /// Queues reclaimed through quiescent states free memory from here.
+    fn quiescent(&self) {}
+
+    /// Announces that the current thread is done with the queue for now.
+    fn offline(&self) {}
+
+    /// Returns a handle for running many operations from the current thread,
+    /// if the queue can do better than running each one on its own.
+    fn handle(&self) -> Option<Box<dyn QueueHandle<T> + '_>> {
+        None
+    }
+}
+
+/// A thread's handle for running many operations on a queue
+// Queues that pin or register on every operation can do it once per handle instead.
+pub trait QueueHandle<T> {
+    fn push(&mut self, elem: T);
+    fn pop(&mut self) -> Option<T>;
+
+    /// Pushes an item unless the queue is full, handing it back if it is.
+    fn try_push(&mut self, elem: T) -> Result<(), T> {
+        self.push(elem);
+        Ok(())
+    }
+
+    /// Pushes every item in `items`, in order.
+    fn push_batch(&mut self, items: &mut dyn Iterator<Item = T>) {
+        for item in items {
+            self.push(item);
+        }
+    }
+
+    /// Pops up to `max` items onto the end of `out`, and returns how many there were.
+    fn pop_batch(&mut self, out: &mut Vec<T>, max: usize) -> usize {
+        pop_each(|| self.pop(), out, max)
+    }
+
+    /// Lets the queue reclaim anything the handle has been holding onto.
+    fn refresh(&mut self) {}
+}
+
+/// Pops with `pop` until there is nothing left, or `max` items have been popped onto `out`.
+fn pop_each<T, F: FnMut() -> Option<T>>(mut pop: F, out: &mut Vec<T>, max: usize) -> usize {
+    let mut popped = 0;
+    while popped < max {
+        match pop() {
+            Some(item) => out.push(item),
+            None => break,
+        }
+        popped += 1;
+    }
+    popped
 }
 
 #[derive(Clone, Debug)]
 pub enum ImplType {
     MutexLock,
     SpinLock,
+    TwoLockMutex,
+    TwoLockSpin,
+    FlatCombining,
     Crossbeam,
     Lockfree,
-    Dirty, 
-    Epoch,
+    Dirty(DirtyReclaim, BackoffType, OrderType, PaddingType),
+    Epoch(ReclaimType, BackoffType, OrderType, PaddingType),
+    Hazard,
+    MichaelScott(ReclaimType),
+    Qsbr,
+    Unrolled(ReclaimType, usize),
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    Tagged,
+    #[cfg(target_arch = "x86_64")]
+    Lcrq,
+    Bounded(usize),
+    Arena(usize),
+    CrossbeamArray(usize),
+    WaitFree,
+    ChaseLev,
+    StdMpsc,
+    LockfreeSpsc,
+    LockfreeSpmc,
+    LockfreeMpsc,
+    LockfreeMpmc,
+    MutexStack,
+    LockfreeStack,
+    Treiber,
+}
+
+impl ImplType {
+    /// Whether this implementation is a LIFO stack rather than a FIFO queue.
+    pub fn is_stack(&self) -> bool {
+        matches!(self, ImplType::MutexStack | ImplType::LockfreeStack | ImplType::Treiber)
+    }
+
+    /// How many items this implementation can hold at once, if it is bounded.
+    pub fn capacity(&self) -> Option<usize> {
+        match self {
+            ImplType::Bounded(capacity) | ImplType::Arena(capacity) | ImplType::CrossbeamArray(capacity) => Some(*capacity),
+            _ => None,
+        }
+    }
 }
 
 /// Constructor function for building queues given an ImplType.
@@ -26,10 +160,91 @@ pub fn create_impl<T: 'static + Sync + Send>(t: &ImplType) -> Box<dyn SyncQueue:
     match t {
         ImplType::MutexLock => Box::new(MutexQueue::<T>::new()),
         ImplType::SpinLock => Box::new(SpinQueue::<T>::new()),
+        ImplType::TwoLockMutex => Box::new(TwoLockMutexQueue::<T>::new()),
+        ImplType::TwoLockSpin => Box::new(TwoLockSpinQueue::<T>::new()),
+        ImplType::FlatCombining => Box::new(FlatCombiningQueue::<T>::new()),
         ImplType::Crossbeam => Box::new(CrossbeamQueue::<T>::new()),
         ImplType::Lockfree => Box::new(LockfreeQueue::<T>::new()),
-        ImplType::Dirty => Box::new(DirtyQueue::<T>::new()),
-        ImplType::Epoch => Box::new(EpochQueue::<T>::new()),
+        ImplType::Dirty(m, b, o, p) => match (o, p) {
+            (OrderType::SeqCst, PaddingType::None) => create_dirty::<T, SeqCstOrder, NoPadding>(*m, *b),
+            (OrderType::SeqCst, PaddingType::Nodes) => create_dirty::<T, SeqCstOrder, PadNodes>(*m, *b),
+            (OrderType::SeqCst, PaddingType::Ends) => create_dirty::<T, SeqCstOrder, PadEnds>(*m, *b),
+            (OrderType::SeqCst, PaddingType::All) => create_dirty::<T, SeqCstOrder, PadAll>(*m, *b),
+            (OrderType::AcqRel, PaddingType::None) => create_dirty::<T, AcqRelOrder, NoPadding>(*m, *b),
+            (OrderType::AcqRel, PaddingType::Nodes) => create_dirty::<T, AcqRelOrder, PadNodes>(*m, *b),
+            (OrderType::AcqRel, PaddingType::Ends) => create_dirty::<T, AcqRelOrder, PadEnds>(*m, *b),
+            (OrderType::AcqRel, PaddingType::All) => create_dirty::<T, AcqRelOrder, PadAll>(*m, *b),
+        },
+        ImplType::Epoch(r, b, o, p) => match (o, p) {
+            (OrderType::SeqCst, PaddingType::None) => create_epoch::<T, SeqCstOrder, NoPadding>(r, *b),
+            (OrderType::SeqCst, PaddingType::Nodes) => create_epoch::<T, SeqCstOrder, PadNodes>(r, *b),
+            (OrderType::SeqCst, PaddingType::Ends) => create_epoch::<T, SeqCstOrder, PadEnds>(r, *b),
+            (OrderType::SeqCst, PaddingType::All) => create_epoch::<T, SeqCstOrder, PadAll>(r, *b),
+            (OrderType::AcqRel, PaddingType::None) => create_epoch::<T, AcqRelOrder, NoPadding>(r, *b),
+            (OrderType::AcqRel, PaddingType::Nodes) => create_epoch::<T, AcqRelOrder, PadNodes>(r, *b),
+            (OrderType::AcqRel, PaddingType::Ends) => create_epoch::<T, AcqRelOrder, PadEnds>(r, *b),
+            (OrderType::AcqRel, PaddingType::All) => create_epoch::<T, AcqRelOrder, PadAll>(r, *b),
+        },
+        ImplType::Hazard => Box::new(HazardQueue::<T>::new()),
+        ImplType::MichaelScott(r) => match r {
+            ReclaimType::Leak => Box::new(MsQueue::<T, Leak>::new()),
+            ReclaimType::Epoch => Box::new(MsQueue::<T, Epoch>::new()),
+            ReclaimType::OwnedEpoch => Box::new(MsQueue::<T, OwnedEpoch>::new()),
+            ReclaimType::Hazard => Box::new(MsQueue::<T, HazardDomain>::new()),
+            ReclaimType::Qsbr => Box::new(MsQueue::<T, QsbrDomain>::new()),
+            ReclaimType::Ebr(config) => Box::new(MsQueue::<T, EbrCollector>::with_reclaimer(
+                EbrCollector::with_config(config.clone()))),
+        },
+        ImplType::Qsbr => Box::new(MsQueue::<T, QsbrDomain>::with_reclaimer(QsbrDomain::with_quiescent_points())),
+        ImplType::Unrolled(r, slots) => match slots {
+            8 => create_unrolled::<T, 8>(r),
+            16 => create_unrolled::<T, 16>(r),
+            32 => create_unrolled::<T, 32>(r),
+            64 => create_unrolled::<T, 64>(r),
+            128 => create_unrolled::<T, 128>(r),
+            _ => panic!("Unrolled queues are only built with 8, 16, 32, 64, or 128 slots per node, not {}!", slots),
+        },
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        ImplType::Tagged => Box::new(TaggedQueue::<T>::new()),
+        #[cfg(target_arch = "x86_64")]
+        ImplType::Lcrq => Box::new(LcrqQueue::<T>::new()),
+        ImplType::Bounded(capacity) => Box::new(BoundedQueue::<T>::new(*capacity)),
+        ImplType::Arena(capacity) => Box::new(ArenaQueue::<T>::new(*capacity)),
+        ImplType::CrossbeamArray(capacity) => Box::new(CrossbeamArrayQueue::<T>::new(*capacity)),
+        ImplType::WaitFree => Box::new(WaitFreeQueue::<T>::new()),
+        _ => panic!("{:?} is not a queue implementation!", t),
+    }
+}
+
+/// Builds a dirty queue with the memory orderings in O and the layout P, that handles popped nodes as `m` says.
+fn create_dirty<T: 'static + Sync + Send, O: MemoryOrder, P: Padding>(m: DirtyReclaim, b: BackoffType) -> Box<dyn SyncQueue::<T>> {
+    match m {
+        DirtyReclaim::Leak => Box::new(DirtyQueue::<T, O, P>::with_backoff(Leak, b)),
+        DirtyReclaim::Deferred => Box::new(DeferredDirtyQueue::<T, O, P>::with_backoff(Deferred::default(), b)),
+    }
+}
+
+/// Builds an epoch queue with the memory orderings in O and the layout P, using the collector `r`.
+fn create_epoch<T: 'static + Sync + Send, O: MemoryOrder, P: Padding>(r: &ReclaimType, b: BackoffType) -> Box<dyn SyncQueue::<T>> {
+    match r {
+        ReclaimType::Epoch => Box::new(EpochQueue::<T, Epoch, O, P>::with_backoff(Epoch, b)),
+        ReclaimType::OwnedEpoch => Box::new(EpochQueue::<T, OwnedEpoch, O, P>::with_backoff(OwnedEpoch::new(), b)),
+        ReclaimType::Ebr(config) => Box::new(EpochQueue::<T, EbrCollector, O, P>::with_backoff(
+            EbrCollector::with_config(config.clone()), b)),
+        _ => panic!("The epoch queue needs an epoch-based collector, not {:?}!", r),
+    }
+}
+
+/// Builds an unrolled queue with K slots per node, using the reclamation scheme `r`.
+fn create_unrolled<T: 'static + Sync + Send, const K: usize>(r: &ReclaimType) -> Box<dyn SyncQueue::<T>> {
+    match r {
+        ReclaimType::Leak => Box::new(UnrolledQueue::<T, Leak, K>::new()),
+        ReclaimType::Epoch => Box::new(UnrolledQueue::<T, Epoch, K>::new()),
+        ReclaimType::OwnedEpoch => Box::new(UnrolledQueue::<T, OwnedEpoch, K>::new()),
+        ReclaimType::Hazard => Box::new(UnrolledQueue::<T, HazardDomain, K>::new()),
+        ReclaimType::Qsbr => Box::new(UnrolledQueue::<T, QsbrDomain, K>::new()),
+        ReclaimType::Ebr(config) => Box::new(UnrolledQueue::<T, EbrCollector, K>::with_reclaimer(
+            EbrCollector::with_config(config.clone()))),
     }
 }
 
@@ -55,6 +270,20 @@ impl<T: Send + Sync> SyncQueue<T> for MutexQueue<T> {
         let mut q = self.lockedq.lock().unwrap();
         q.push_back(elem);
     }
+
+    fn push_batch(&self, items: &mut dyn Iterator<Item = T>) {
+        // The iterator may do any amount of work, so run it before taking the lock.
+        let items: Vec<T> = items.collect();
+        let mut q = self.lockedq.lock().unwrap();
+        q.extend(items);
+    }
+
+    fn pop_batch(&self, out: &mut Vec<T>, max: usize) -> usize {
+        let mut q = self.lockedq.lock().unwrap();
+        let n = max.min(q.len());
+        out.extend(q.drain(..n));
+        n
+    }
 }
 
 /// MPMC Queue implemented with spinlocks
@@ -79,6 +308,20 @@ impl<T: Send + Sync> SyncQueue<T> for SpinQueue<T> {
         let mut q = self.lockedq.lock();
         q.push_back(elem);
     }
+
+    fn push_batch(&self, items: &mut dyn Iterator<Item = T>) {
+        // The iterator may do any amount of work, so run it before taking the lock.
+        let items: Vec<T> = items.collect();
+        let mut q = self.lockedq.lock();
+        q.extend(items);
+    }
+
+    fn pop_batch(&self, out: &mut Vec<T>, max: usize) -> usize {
+        let mut q = self.lockedq.lock();
+        let n = max.min(q.len());
+        out.extend(q.drain(..n));
+        n
+    }
 }
 
 /// MPMC Queue implemented as a Michael Scott segmented lockfree queue
@@ -104,6 +347,37 @@ impl<T: Send + Sync> SyncQueue<T> for CrossbeamQueue<T> {
     }
 }
 
+/// Bounded MPMC Queue implemented as a ring buffer
+/// using the crossbeam crate
+struct CrossbeamArrayQueue<T> {
+    q: ArrayQueue<T>,
+}
+
+impl<T> CrossbeamArrayQueue<T> {
+    pub fn new(capacity: usize) -> CrossbeamArrayQueue<T> {
+        CrossbeamArrayQueue { q: ArrayQueue::new(capacity), }
+    }
+}
+
+
+impl<T: Send + Sync> SyncQueue<T> for CrossbeamArrayQueue<T> {
+    fn pop(&self) -> Option<T> {
+        self.q.pop().ok()
+    }
+
+    fn push(&self, elem: T) {
+        let mut elem = elem;
+        while let Err(rejected) = self.try_push(elem) {
+            elem = rejected;
+            thread::yield_now();
+        }
+    }
+
+    fn try_push(&self, elem: T) -> Result<(), T> {
+        self.q.push(elem).map_err(|e| e.0)
+    }
+}
+
 /// MPMC lockfree queue from the lockfree crate
 struct LockfreeQueue<T> {
     q: LFQueue<T>,
@@ -126,3 +400,15 @@ impl<T: Send + Sync> SyncQueue<T> for LockfreeQueue<T> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use queue_tests::check_concurrent_fifo;
+
+    #[test]
+    fn locked_batches_keep_order() {
+        check_concurrent_fifo(&MutexQueue::new(), 4, 4, 20_000, 16);
+        check_concurrent_fifo(&SpinQueue::new(), 4, 4, 20_000, 16);
+    }
+}
@@ -0,0 +1,116 @@
+use std::sync::Mutex;
+use lockfree::stack::Stack as LFStack;
+use sync_queue::{SyncQueue, ImplType};
+use treiber_stack::TreiberStack;
+
+pub trait SyncStack<T>: Send + Sync {
+    fn pop(&self) -> Option<T>;
+    fn push(&self, elem: T);
+}
+
+/// Constructor function for building stacks given an ImplType.
+pub fn create_stack_impl<T: 'static + Sync + Send>(t: &ImplType) -> Box<dyn SyncStack::<T>> {
+    match t {
+        ImplType::MutexStack => Box::new(MutexStack::<T>::new()),
+        ImplType::LockfreeStack => Box::new(LockfreeStack::<T>::new()),
+        ImplType::Treiber => Box::new(TreiberStack::<T>::new()),
+        _ => panic!("{:?} is not a stack implementation!", t),
+    }
+}
+
+/// Constructor function for building stacks that run wherever a queue is expected.
+pub fn create_stack_queue<T: 'static + Sync + Send>(t: &ImplType) -> Box<dyn SyncQueue::<T>> {
+    Box::new(StackQueue { stack: create_stack_impl(t) })
+}
+
+/// Runs a stack as a queue that hands items back in LIFO order
+// This lets stacks be wrapped like any queue, say to block or to close them.
+struct StackQueue<T> {
+    stack: Box<dyn SyncStack<T>>,
+}
+
+impl<T: Send + Sync> SyncQueue<T> for StackQueue<T> {
+    fn pop(&self) -> Option<T> {
+        self.stack.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.stack.push(elem)
+    }
+}
+
+/// MPMC Stack implemented with mutexes
+struct MutexStack<T> {
+    lockeds: Mutex<Vec<T>>,
+}
+
+impl<T> MutexStack<T> {
+    pub fn new() -> MutexStack<T> {
+        MutexStack { lockeds: Mutex::new(Vec::new()), }
+    }
+}
+
+
+impl<T: Send + Sync> SyncStack<T> for MutexStack<T> {
+    fn pop(&self) -> Option<T> {
+        let mut s = self.lockeds.lock().unwrap();
+        s.pop()
+    }
+
+    fn push(&self, elem: T) {
+        let mut s = self.lockeds.lock().unwrap();
+        s.push(elem);
+    }
+}
+
+/// MPMC lockfree stack from the lockfree crate
+struct LockfreeStack<T> {
+    s: LFStack<T>,
+}
+
+impl<T> LockfreeStack<T> {
+    pub fn new() -> LockfreeStack<T> {
+        LockfreeStack { s: LFStack::new(), }
+    }
+}
+
+
+impl<T: Send + Sync> SyncStack<T> for LockfreeStack<T> {
+    fn pop(&self) -> Option<T> {
+        self.s.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.s.push(elem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use queue_tests::{check_sequential_lifo, check_concurrent_stack};
+
+    #[test]
+    fn sequential_lifo() {
+        check_sequential_lifo(&MutexStack::new(), 1000);
+        check_sequential_lifo(&LockfreeStack::new(), 1000);
+    }
+
+    #[test]
+    fn concurrent_no_loss() {
+        check_concurrent_stack(&MutexStack::new(), 4, 4, 20_000);
+        check_concurrent_stack(&LockfreeStack::new(), 4, 4, 20_000);
+    }
+
+    #[test]
+    fn stack_queue_pops_lifo() {
+        let queue = create_stack_queue::<u64>(&ImplType::Treiber);
+        for i in 0..10 {
+            queue.push(i);
+        }
+        for i in (0..10).rev() {
+            assert_eq!(queue.pop(), Some(i));
+        }
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -0,0 +1,259 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::ptr;
+use std::mem::MaybeUninit;
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use sync_queue::SyncQueue;
+
+// On x86_64 Linux, user space addresses only use the low 47 bits of a pointer.
+// The upper 16 bits of every head, tail and next word hold a modification
+// counter, so a single-word CAS also checks that the pointer was not reused.
+// Being 16 bits, the counter wraps after 65536 modifications of the same word.
+// A thread that stalls between loading a word and CASing it, for exactly a
+// multiple of that many modifications, and then finds the same pointer again,
+// would wrongly succeed. That takes a very unlucky stall, but nothing rules it out.
+const PTR_BITS: usize = 48;
+const PTR_MASK: usize = (1 << PTR_BITS) - 1;
+
+/// Packs a pointer and a modification counter into one word.
+fn pack<T>(p: *mut Node<T>, tag: usize) -> usize {
+    debug_assert!(p as usize & !PTR_MASK == 0, "pointer does not fit in {} bits", PTR_BITS);
+    (p as usize) | (tag << PTR_BITS)
+}
+
+fn ptr_of<T>(word: usize) -> *mut Node<T> {
+    (word & PTR_MASK) as *mut Node<T>
+}
+
+fn tag_of(word: usize) -> usize {
+    word >> PTR_BITS
+}
+
+/// Stores data and tagged next pointers for items in the queue
+pub struct Node<T> {
+    // The UnsafeCell wrapper is required to allow unsafe operations on this data,
+    // specifically moving the T object out of raw pointers.
+    // The MaybeUninit wrapper allows for uninitialized nodes to be created.
+    pub data: UnsafeCell<MaybeUninit<T>>,
+    // Tagged pointer to the next node, shared by the queue and the free list.
+    pub next: AtomicUsize,
+    // How many of the two poppers a node has to wait for before it can be
+    // recycled are done with it: the one that takes its data, and the one
+    // that unlinks it once it has become the sentinel.
+    released: AtomicUsize,
+}
+
+impl<T> Node<T> {
+    fn new() -> Node<T> {
+        Node {
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+            next: AtomicUsize::new(0),
+            released: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// ABA-safe lockfree queue based on the Michael-Scott queue design
+// This is the original counted-pointer algorithm from the Michael-Scott paper.
+// Popped nodes are recycled through a tagged Treiber free list instead of being
+// returned to the allocator, so a thread holding a stale pointer can always
+// load its next word safely. The tags make sure its CAS then fails.
+// Unlike the paper, a popper only reads the data after its CAS wins, so no one
+// reads it while a pusher may be writing a recycled node. Instead, a node is
+// only recycled once the popper that took its data is done reading it.
+pub struct TaggedQueue<T> {
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    free: AtomicUsize,
+    _marker: PhantomData<*mut Node<T>>,
+}
+
+unsafe impl<T: Send> Send for TaggedQueue<T> {}
+unsafe impl<T: Send> Sync for TaggedQueue<T> {}
+
+impl<T> TaggedQueue<T> {
+    pub fn new() -> TaggedQueue<T> {
+        // Initializes the queue with an empty node. This makes the push/pop
+        // logic much simpler.
+        let empty_node = Box::into_raw(Box::new(Node::<T>::new()));
+        // The first sentinel has no data to wait for.
+        unsafe { (*empty_node).released.store(1, Ordering::Relaxed) };
+        TaggedQueue {
+            head: AtomicUsize::new(pack(empty_node, 0)),
+            tail: AtomicUsize::new(pack(empty_node, 0)),
+            free: AtomicUsize::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Takes a node off of the free list, or allocates one if it is empty.
+    fn alloc_node(&self) -> *mut Node<T> {
+        loop {
+            let top = self.free.load(Ordering::SeqCst);
+            let node = ptr_of::<T>(top);
+            if node.is_null() {
+                return Box::into_raw(Box::new(Node::new()))
+            }
+            // The node may be taken by someone else in the meantime, but since nodes
+            // are never freed, reading its next word is safe. The tag catches reuse.
+            let next = unsafe { (*node).next.load(Ordering::SeqCst) };
+            let new_top = pack(ptr_of::<T>(next), tag_of(top) + 1);
+            if self.free.compare_exchange(top, new_top, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return node
+            }
+        }
+    }
+
+    /// Returns a node to the free list.
+    fn free_node(&self, node: *mut Node<T>) {
+        loop {
+            let top = self.free.load(Ordering::SeqCst);
+            let next = unsafe { (*node).next.load(Ordering::SeqCst) };
+            unsafe { (*node).next.store(pack(ptr_of::<T>(top), tag_of(next) + 1), Ordering::SeqCst) };
+            let new_top = pack(node, tag_of(top) + 1);
+            if self.free.compare_exchange(top, new_top, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                return
+            }
+        }
+    }
+
+    /// Marks one of the two poppers as done with `node`, and recycles it if it was the last.
+    fn release_node(&self, node: *mut Node<T>) {
+        if unsafe { (*node).released.fetch_add(1, Ordering::SeqCst) } == 1 {
+            self.free_node(node);
+        }
+    }
+
+    pub fn push(&self, item: T) {
+        let new_node = self.alloc_node();
+        unsafe {
+            (*new_node).data.get().write(MaybeUninit::new(item));
+            (*new_node).released.store(0, Ordering::Relaxed);
+            let next = (*new_node).next.load(Ordering::SeqCst);
+            (*new_node).next.store(pack(ptr::null_mut::<Node<T>>(), tag_of(next) + 1), Ordering::SeqCst);
+        }
+
+        let mut tail: usize;
+        loop {
+            tail = self.tail.load(Ordering::SeqCst);
+            let tail_ptr = ptr_of::<T>(tail);
+
+            // grab the next pointer and make sure that tail has not changed under us
+            let next = unsafe { (*tail_ptr).next.load(Ordering::SeqCst) };
+            if tail != self.tail.load(Ordering::SeqCst) {
+                continue
+            }
+
+            if ptr_of::<T>(next).is_null() {
+                // if CAS succeeds on the tail's next pointer, then we can commit our push
+                let new_next = pack(new_node, tag_of(next) + 1);
+                if unsafe { (*tail_ptr).next.compare_exchange(next, new_next, Ordering::SeqCst, Ordering::SeqCst) }
+                            .is_ok() {
+                    break
+                }
+            } else {
+                // the tail is lagging behind, so help swing it forward
+                let new_tail = pack(ptr_of::<T>(next), tag_of(tail) + 1);
+                let _ = self.tail.compare_exchange(tail, new_tail, Ordering::SeqCst, Ordering::SeqCst);
+            }
+        }
+        // commit our push to the queue
+        let new_tail = pack(new_node, tag_of(tail) + 1);
+        let _ = self.tail.compare_exchange(tail, new_tail, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        loop {
+            let head = self.head.load(Ordering::SeqCst);
+            let tail = self.tail.load(Ordering::SeqCst);
+            let head_ptr = ptr_of::<T>(head);
+
+            // grab the next pointer and make sure the head hasn't changed
+            let next = unsafe { (*head_ptr).next.load(Ordering::SeqCst) };
+            if head != self.head.load(Ordering::SeqCst) {
+                continue
+            }
+            let next_ptr = ptr_of::<T>(next);
+
+            if head_ptr == ptr_of::<T>(tail) {
+                // if there are no more nodes, the queue is empty
+                if next_ptr.is_null() {
+                    return None
+                }
+                // the tail is lagging behind, so help swing it forward
+                let new_tail = pack(next_ptr, tag_of(tail) + 1);
+                let _ = self.tail.compare_exchange(tail, new_tail, Ordering::SeqCst, Ordering::SeqCst);
+                continue
+            }
+
+            let new_head = pack(next_ptr, tag_of(head) + 1);
+            if self.head.compare_exchange(head, new_head, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                // Next is the sentinel now, but it cannot be recycled until we release it,
+                // so no one else touches its data in the meantime.
+                let data = unsafe { (*next_ptr).data.get().read().assume_init() };
+                self.release_node(next_ptr);
+                self.release_node(head_ptr);
+                return Some(data)
+            }
+        }
+    }
+}
+
+impl<T> Default for TaggedQueue<T> {
+    fn default() -> TaggedQueue<T> {
+        TaggedQueue::new()
+    }
+}
+
+impl<T> Drop for TaggedQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+        unsafe {
+            drop(Box::from_raw(ptr_of::<T>(*self.head.get_mut())));
+            let mut node = ptr_of::<T>(*self.free.get_mut());
+            while !node.is_null() {
+                let next = ptr_of::<T>((*node).next.load(Ordering::Relaxed));
+                drop(Box::from_raw(node));
+                node = next;
+            }
+        }
+    }
+}
+
+impl<T: Send + Sync> SyncQueue<T> for TaggedQueue<T> {
+    fn pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.push(elem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use queue_tests::{check_sequential_fifo, check_concurrent_fifo};
+
+    #[test]
+    fn tags_wrap_without_touching_the_pointer() {
+        let node = Box::into_raw(Box::new(Node::<u64>::new()));
+        let max_tag = (1 << (64 - PTR_BITS)) - 1;
+        let word = pack(node, max_tag);
+        assert_eq!(tag_of(word), max_tag);
+        let wrapped = pack(ptr_of::<u64>(word), tag_of(word) + 1);
+        assert_eq!(ptr_of::<u64>(wrapped), node);
+        assert_eq!(tag_of(wrapped), 0);
+        unsafe { drop(Box::from_raw(node)) };
+    }
+
+    #[test]
+    fn sequential_fifo() {
+        check_sequential_fifo(&TaggedQueue::new(), 1000);
+    }
+
+    #[test]
+    fn concurrent_no_loss() {
+        check_concurrent_fifo(&TaggedQueue::new(), 4, 4, 20_000, 1);
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Maximum number of threads that can hold an id at the same time.
+pub const MAX_THREADS: usize = 128;
+
+// Ids are handed out densely and recycled when a thread exits, so per-thread
+// arrays only need to be as large as the number of live threads.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+static FREE_IDS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+/// Owns an id for the lifetime of a thread
+struct ThreadId(usize);
+
+impl ThreadId {
+    fn new() -> ThreadId {
+        if let Some(id) = FREE_IDS.lock().unwrap().pop() {
+            return ThreadId(id)
+        }
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        assert!(id < MAX_THREADS, "more than {} threads are alive", MAX_THREADS);
+        ThreadId(id)
+    }
+}
+
+impl Drop for ThreadId {
+    fn drop(&mut self) {
+        FREE_IDS.lock().unwrap().push(self.0);
+    }
+}
+
+thread_local! {
+    static THREAD_ID: ThreadId = ThreadId::new();
+}
+
+/// Returns the id of the current thread, which is below `high_water()`.
+pub fn current() -> usize {
+    THREAD_ID.with(|id| id.0)
+}
+
+/// Returns one more than the largest id handed out so far.
+pub fn high_water() -> usize {
+    NEXT_ID.load(Ordering::SeqCst)
+}
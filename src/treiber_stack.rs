@@ -0,0 +1,111 @@
+use std::sync::atomic::Ordering;
+use std::mem::MaybeUninit;
+use crossbeam_epoch as epoch;
+use crossbeam_epoch::{Atomic, Owned};
+use sync_stack::SyncStack;
+
+/// Stores data and next pointers for items in the stack
+pub struct Node<T> {
+    // The MaybeUninit wrapper lets us move the data out of a shared node.
+    pub data: MaybeUninit<T>,
+    // This pointer to the next node is atomic to allow CAS.
+    pub next: Atomic<Node<T>>,
+}
+
+/// Custom lockfree stack based on Treiber's design
+// A single CAS on the top pointer linearizes both push and pop.
+// Popped nodes are retired to crossbeam's epoch collector, which also
+// protects us from ABA, since a node cannot be reused while anyone holds it.
+pub struct TreiberStack<T> {
+    top: Atomic<Node<T>>,
+}
+
+impl<T> TreiberStack<T> {
+    pub fn new() -> TreiberStack<T> {
+        TreiberStack {
+            top: Atomic::null(),
+        }
+    }
+
+    pub fn push(&self, item: T) {
+        let mut new_node = Owned::new(Node {
+            data: MaybeUninit::new(item),
+            next: Atomic::null(),
+        });
+
+        let guard = &epoch::pin(); // enter data structure
+        loop {
+            let shared_top = self.top.load(Ordering::SeqCst, guard);
+            new_node.next.store(shared_top, Ordering::Relaxed);
+
+            // Try to swing the top pointer to our new node.
+            match self.top.compare_and_set(shared_top, new_node, Ordering::SeqCst, guard) {
+                Ok(_) => return,
+                // Someone beat us to it, so we take our node back and retry.
+                Err(e) => new_node = e.new,
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let guard = &epoch::pin(); // enter data structure
+        loop {
+            let shared_top = self.top.load(Ordering::SeqCst, guard);
+            match unsafe { shared_top.as_ref() } {
+                // Found something on the stack!
+                Some(raw_top) => {
+                    let shared_next = raw_top.next.load(Ordering::SeqCst, guard);
+                    // Let's try to unlink the top node.
+                    if self.top.compare_and_set(shared_top, shared_next, Ordering::SeqCst, guard).is_ok() {
+                        // Success! We have exclusive access to the data now.
+                        unsafe {
+                            guard.defer_destroy(shared_top);
+                            return Some(raw_top.data.as_ptr().read())
+                        }
+                    }
+                    // Someone beat us to it! Let's retry.
+                },
+                // Nothing on the stack.
+                None => return None,
+            }
+        }
+    }
+}
+
+impl<T> Default for TreiberStack<T> {
+    fn default() -> TreiberStack<T> {
+        TreiberStack::new()
+    }
+}
+
+impl<T> Drop for TreiberStack<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+impl<T: Send + Sync> SyncStack<T> for TreiberStack<T> {
+    fn pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.push(elem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use queue_tests::{check_sequential_lifo, check_concurrent_stack};
+
+    #[test]
+    fn sequential_lifo() {
+        check_sequential_lifo(&TreiberStack::new(), 1000);
+    }
+
+    #[test]
+    fn concurrent_no_loss() {
+        check_concurrent_stack(&TreiberStack::new(), 4, 4, 20_000);
+    }
+}
@@ -0,0 +1,227 @@
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Mutex;
+use std::ptr;
+use std::mem::MaybeUninit;
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use spin::Mutex as Spinlock;
+use sync_queue::SyncQueue;
+
+/// A lock around one end of the queue
+// This lets the same queue run on std and spin mutexes.
+pub trait EndLock<P>: Send + Sync {
+    fn new(p: P) -> Self;
+    fn with<R, F: FnOnce(&mut P) -> R>(&self, f: F) -> R;
+}
+
+impl<P: Send> EndLock<P> for Mutex<P> {
+    fn new(p: P) -> Mutex<P> {
+        Mutex::new(p)
+    }
+
+    fn with<R, F: FnOnce(&mut P) -> R>(&self, f: F) -> R {
+        f(&mut self.lock().unwrap())
+    }
+}
+
+impl<P: Send> EndLock<P> for Spinlock<P> {
+    fn new(p: P) -> Spinlock<P> {
+        Spinlock::new(p)
+    }
+
+    fn with<R, F: FnOnce(&mut P) -> R>(&self, f: F) -> R {
+        f(&mut self.lock())
+    }
+}
+
+/// Stores data and next pointers for items in the queue
+pub struct Node<T> {
+    // The UnsafeCell wrapper is required to move the T object out of a shared node.
+    // The MaybeUninit wrapper allows for uninitialized (sentinel) nodes.
+    pub data: UnsafeCell<MaybeUninit<T>>,
+    // When the queue is empty, a pusher writes this while a popper reads it,
+    // each under a different lock, so it has to be atomic.
+    pub next: AtomicPtr<Node<T>>,
+}
+
+impl<T> Node<T> {
+    fn new() -> Node<T> {
+        Node {
+            data: UnsafeCell::new(MaybeUninit::uninit()),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+/// A node pointer guarded by one of the queue's locks
+// Raw node pointers are only touched while holding the lock that guards them.
+pub struct NodePtr<T>(*mut Node<T>);
+unsafe impl<T: Send> Send for NodePtr<T> {}
+
+/// MPMC Queue with separate head and tail locks
+// This is the two-lock queue from the Michael-Scott paper. A sentinel node
+// keeps pushers and poppers from ever touching the same node's data, so
+// one pusher and one popper can run at the same time.
+pub struct TwoLockQueue<T, L: EndLock<NodePtr<T>>> {
+    head: L,
+    tail: L,
+    _marker: PhantomData<T>,
+}
+
+/// Two-lock queue using std::sync::Mutex
+pub type TwoLockMutexQueue<T> = TwoLockQueue<T, Mutex<NodePtr<T>>>;
+/// Two-lock queue using spin::Mutex
+pub type TwoLockSpinQueue<T> = TwoLockQueue<T, Spinlock<NodePtr<T>>>;
+
+impl<T, L: EndLock<NodePtr<T>>> TwoLockQueue<T, L> {
+    pub fn new() -> TwoLockQueue<T, L> {
+        // Initializes the queue with an empty (sentinel) node.
+        let empty_node = Box::into_raw(Box::new(Node::new()));
+        TwoLockQueue {
+            head: L::new(NodePtr(empty_node)),
+            tail: L::new(NodePtr(empty_node)),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn push(&self, item: T) {
+        let new_node = TwoLockQueue::<T, L>::new_node(item);
+        self.tail.with(|tail| {
+            unsafe { (*tail.0).next.store(new_node, Ordering::Release) };
+            tail.0 = new_node;
+        });
+    }
+
+    /// Pushes every item in `items` while taking the tail lock once.
+    pub fn push_batch<I: IntoIterator<Item = T>>(&self, items: I) {
+        let mut items = items.into_iter();
+        let first = match items.next() {
+            Some(item) => TwoLockQueue::<T, L>::new_node(item),
+            None => return,
+        };
+        // The chain is built outside of the lock, since no one else can see it yet.
+        let mut last = first;
+        for item in items {
+            let node = TwoLockQueue::<T, L>::new_node(item);
+            unsafe { (*last).next.store(node, Ordering::Relaxed) };
+            last = node;
+        }
+        self.tail.with(|tail| {
+            unsafe { (*tail.0).next.store(first, Ordering::Release) };
+            tail.0 = last;
+        });
+    }
+
+    fn new_node(item: T) -> *mut Node<T> {
+        let node = Box::into_raw(Box::new(Node::new()));
+        unsafe { (*node).data.get().write(MaybeUninit::new(item)) }
+        node
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let old_head = self.head.with(|head| {
+            let next = unsafe { (*head.0).next.load(Ordering::Acquire) };
+            if next.is_null() {
+                return None
+            }
+            // next becomes the new sentinel, so we can move its data out.
+            let result = unsafe { (*next).data.get().read().assume_init() };
+            let old_head = head.0;
+            head.0 = next;
+            Some((old_head, result))
+        });
+        // The old sentinel can be freed outside of the lock.
+        old_head.map(|(old_head, result)| {
+            unsafe { drop(Box::from_raw(old_head)) };
+            result
+        })
+    }
+
+    /// Pops up to `max` items onto the end of `out` while taking the head lock once,
+    /// and returns how many there were.
+    pub fn pop_batch(&self, out: &mut Vec<T>, max: usize) -> usize {
+        let (old_head, popped) = self.head.with(|head| {
+            let old_head = head.0;
+            let mut popped = 0;
+            while popped < max {
+                let next = unsafe { (*head.0).next.load(Ordering::Acquire) };
+                if next.is_null() {
+                    break
+                }
+                out.push(unsafe { (*next).data.get().read().assume_init() });
+                head.0 = next;
+                popped += 1;
+            }
+            (old_head, popped)
+        });
+        // The old sentinels are off the queue and already linked to each other,
+        // so they can be freed outside of the lock.
+        let mut node = old_head;
+        for _ in 0..popped {
+            let next = unsafe { (*node).next.load(Ordering::Relaxed) };
+            unsafe { drop(Box::from_raw(node)) };
+            node = next;
+        }
+        popped
+    }
+}
+
+impl<T, L: EndLock<NodePtr<T>>> Default for TwoLockQueue<T, L> {
+    fn default() -> TwoLockQueue<T, L> {
+        TwoLockQueue::new()
+    }
+}
+
+impl<T, L: EndLock<NodePtr<T>>> Drop for TwoLockQueue<T, L> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+        let sentinel = self.head.with(|head| head.0);
+        unsafe { drop(Box::from_raw(sentinel)) };
+    }
+}
+
+impl<T: Send + Sync, L: EndLock<NodePtr<T>>> SyncQueue<T> for TwoLockQueue<T, L> {
+    fn pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.push(elem)
+    }
+
+    fn push_batch(&self, items: &mut dyn Iterator<Item = T>) {
+        self.push_batch(items)
+    }
+
+    fn pop_batch(&self, out: &mut Vec<T>, max: usize) -> usize {
+        self.pop_batch(out, max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use queue_tests::{check_sequential_fifo, check_concurrent_fifo};
+
+    #[test]
+    fn sequential_fifo() {
+        check_sequential_fifo(&TwoLockMutexQueue::new(), 1000);
+        check_sequential_fifo(&TwoLockSpinQueue::new(), 1000);
+    }
+
+    #[test]
+    fn concurrent_no_loss_mutex() {
+        check_concurrent_fifo(&TwoLockMutexQueue::new(), 4, 4, 20_000, 1);
+    }
+
+    #[test]
+    fn concurrent_no_loss_spin() {
+        check_concurrent_fifo(&TwoLockSpinQueue::new(), 4, 4, 20_000, 1);
+    }
+
+    #[test]
+    fn batches_keep_order() {
+        check_concurrent_fifo(&TwoLockMutexQueue::new(), 4, 4, 20_000, 16);
+        check_concurrent_fifo(&TwoLockSpinQueue::new(), 4, 4, 20_000, 16);
+    }
+}
@@ -0,0 +1,298 @@
+use std::sync::atomic::{AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+use std::ptr;
+use std::array;
+use std::mem::MaybeUninit;
+use std::cell::UnsafeCell;
+use reclaim::{Reclaimer, ReclaimGuard, Epoch};
+use sync_queue::SyncQueue;
+
+/// Slot states. A slot only ever moves forward through these.
+const EMPTY: u8 = 0;
+const FULL: u8 = 1;
+// A popper got to the slot before its pusher, so the pusher has to go elsewhere.
+const TAKEN: u8 = 2;
+
+/// A single item in a node
+pub struct Slot<T> {
+    pub state: AtomicU8,
+    // The MaybeUninit wrapper allows for empty slots.
+    pub data: UnsafeCell<MaybeUninit<T>>,
+}
+
+impl<T> Slot<T> {
+    /// Puts `item` in a slot its pusher claimed, or hands it back if a popper already gave up on the slot.
+    fn fill(&self, item: T) -> Result<(), T> {
+        unsafe { self.data.get().write(MaybeUninit::new(item)) };
+        if self.state.compare_exchange(EMPTY, FULL, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+            return Ok(())
+        }
+        // The popper will never look at it, so take the item back.
+        Err(unsafe { self.data.get().read().assume_init() })
+    }
+}
+
+/// Stores up to K items, along with how far pushers and poppers have gotten
+pub struct Node<T, const K: usize> {
+    // Pushers and poppers each claim a slot with a fetch-and-add on their index.
+    // Both keep counting past K once the node is used up.
+    pub enq_idx: AtomicUsize,
+    pub deq_idx: AtomicUsize,
+    pub slots: [Slot<T>; K],
+    pub next: AtomicPtr<Node<T, K>>,
+}
+
+impl<T, const K: usize> Node<T, K> {
+    /// Creates a node whose first slot already holds `item`.
+    fn new(item: T) -> Node<T, K> {
+        let node = Node {
+            enq_idx: AtomicUsize::new(1),
+            deq_idx: AtomicUsize::new(0),
+            slots: array::from_fn(|_| Slot {
+                state: AtomicU8::new(EMPTY),
+                data: UnsafeCell::new(MaybeUninit::uninit()),
+            }),
+            next: AtomicPtr::new(ptr::null_mut()),
+        };
+        unsafe { node.slots[0].data.get().write(MaybeUninit::new(item)) };
+        node.slots[0].state.store(FULL, Ordering::Relaxed);
+        node
+    }
+
+    /// Creates a node with nothing left to pop, to start the queue off with.
+    fn sentinel() -> Node<T, K> {
+        Node {
+            enq_idx: AtomicUsize::new(K),
+            deq_idx: AtomicUsize::new(K),
+            slots: array::from_fn(|_| Slot {
+                state: AtomicU8::new(TAKEN),
+                data: UnsafeCell::new(MaybeUninit::uninit()),
+            }),
+            next: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+/// Unrolled lockfree queue based on the Michael-Scott queue design
+// Every node holds K items instead of one, following Ramalhete and Correia's
+// FAAArrayQueue. Within a node, pushers and poppers claim slots with a
+// fetch-and-add instead of a CAS loop, and only fall back to Michael-Scott
+// style linking once the node is used up. Nodes are allocated, and retired
+// through the reclaimer, once per K items rather than once per item.
+// With the Leak reclaimer this is the unrolled counterpart of DirtyQueue,
+// and with Epoch it is the counterpart of EpochQueue.
+// crossbeam's SegQueue is built along the same lines.
+pub struct UnrolledQueue<T, R: Reclaimer = Epoch, const K: usize = 32> {
+    head: AtomicPtr<Node<T, K>>,
+    tail: AtomicPtr<Node<T, K>>,
+    reclaimer: R,
+}
+
+unsafe impl<T: Send, R: Reclaimer, const K: usize> Send for UnrolledQueue<T, R, K> {}
+unsafe impl<T: Send, R: Reclaimer, const K: usize> Sync for UnrolledQueue<T, R, K> {}
+
+impl<T, R: Reclaimer, const K: usize> UnrolledQueue<T, R, K> {
+    pub fn new() -> UnrolledQueue<T, R, K> {
+        UnrolledQueue::with_reclaimer(R::default())
+    }
+
+    pub fn with_reclaimer(reclaimer: R) -> UnrolledQueue<T, R, K> {
+        assert!(K > 0, "nodes need at least one slot");
+        let sentinel = Box::into_raw(Box::new(Node::sentinel()));
+        UnrolledQueue {
+            head: AtomicPtr::new(sentinel),
+            tail: AtomicPtr::new(sentinel),
+            reclaimer,
+        }
+    }
+
+    pub fn push(&self, item: T) {
+        let mut item = item;
+        let guard = self.reclaimer.pin();
+        loop {
+            let tail = guard.protect(0, &self.tail);
+            let raw_tail = unsafe { &*tail };
+            let idx = raw_tail.enq_idx.fetch_add(1, Ordering::SeqCst);
+
+            if idx < K {
+                // We own this slot, unless a popper already gave up on it.
+                match raw_tail.slots[idx].fill(item) {
+                    Ok(()) => return,
+                    Err(rejected) => item = rejected,
+                }
+                continue
+            }
+
+            // This node is full, so link a new one holding our item.
+            if tail != self.tail.load(Ordering::SeqCst) {
+                continue
+            }
+            let next = raw_tail.next.load(Ordering::SeqCst);
+            if next.is_null() {
+                let new_node = Box::into_raw(Box::new(Node::new(item)));
+                match raw_tail.next.compare_exchange(ptr::null_mut(), new_node, Ordering::SeqCst, Ordering::SeqCst) {
+                    Ok(_) => {
+                        let _ = self.tail.compare_exchange(tail, new_node, Ordering::SeqCst, Ordering::SeqCst);
+                        return
+                    },
+                    Err(_) => {
+                        // Someone else linked a node first, so take our item back out of ours.
+                        let new_node = unsafe { Box::from_raw(new_node) };
+                        item = unsafe { new_node.slots[0].data.get().read().assume_init() };
+                    },
+                }
+            } else {
+                // the tail is lagging behind, so help swing it forward
+                let _ = self.tail.compare_exchange(tail, next, Ordering::SeqCst, Ordering::SeqCst);
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let guard = self.reclaimer.pin();
+        loop {
+            let head = guard.protect(0, &self.head);
+            let raw_head = unsafe { &*head };
+            if raw_head.deq_idx.load(Ordering::SeqCst) >= raw_head.enq_idx.load(Ordering::SeqCst)
+                    && raw_head.next.load(Ordering::SeqCst).is_null() {
+                return None
+            }
+
+            let idx = raw_head.deq_idx.fetch_add(1, Ordering::SeqCst);
+            if idx < K {
+                let slot = &raw_head.slots[idx];
+                // If the pusher has not filled this slot yet, it never will.
+                if slot.state.swap(TAKEN, Ordering::SeqCst) == FULL {
+                    return Some(unsafe { slot.data.get().read().assume_init() })
+                }
+                continue
+            }
+
+            // This node is drained, so move on to the next one, if there is one.
+            let next = raw_head.next.load(Ordering::SeqCst);
+            if next.is_null() {
+                return None
+            }
+            // The tail must not be left pointing at a node we are about to retire.
+            let _ = self.tail.compare_exchange(head, next, Ordering::SeqCst, Ordering::SeqCst);
+            if self.head.compare_exchange(head, next, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                unsafe { guard.retire(head) };
+            }
+        }
+    }
+}
+
+impl<T, R: Reclaimer, const K: usize> Default for UnrolledQueue<T, R, K> {
+    fn default() -> UnrolledQueue<T, R, K> {
+        UnrolledQueue::new()
+    }
+}
+
+impl<T, R: Reclaimer, const K: usize> Drop for UnrolledQueue<T, R, K> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+        unsafe { drop(Box::from_raw(*self.head.get_mut())) };
+    }
+}
+
+impl<T: Send + Sync, R: Reclaimer, const K: usize> SyncQueue<T> for UnrolledQueue<T, R, K> {
+    fn pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.push(elem)
+    }
+
+    fn quiescent(&self) {
+        self.reclaimer.quiescent()
+    }
+
+    fn offline(&self) {
+        self.reclaimer.offline()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use reclaim::{Retired, Leak};
+    use hazard::HazardDomain;
+    use queue_tests::{check_sequential_fifo, check_concurrent_fifo};
+
+    /// Frees retired nodes right away, and counts them, for single-threaded tests
+    #[derive(Default)]
+    struct CountRetired {
+        retired: AtomicUsize,
+    }
+
+    struct CountGuard<'a>(&'a CountRetired);
+
+    impl Reclaimer for CountRetired {
+        type Guard<'a> = CountGuard<'a>;
+
+        fn pin(&self) -> CountGuard<'_> {
+            CountGuard(self)
+        }
+    }
+
+    impl<'a> ReclaimGuard for CountGuard<'a> {
+        fn protect<U>(&self, _slot: usize, src: &AtomicPtr<U>) -> *mut U {
+            src.load(Ordering::SeqCst)
+        }
+
+        unsafe fn defer_free(&self, r: Retired) {
+            self.0.retired.fetch_add(1, Ordering::SeqCst);
+            r.free();
+        }
+    }
+
+    #[test]
+    fn sequential_fifo_across_nodes() {
+        check_sequential_fifo(&UnrolledQueue::<u64, Leak, 8>::new(), 8 * 3 + 3);
+    }
+
+    #[test]
+    fn drained_nodes_are_retired() {
+        let queue = UnrolledQueue::<u64, CountRetired, 4>::new();
+        for i in 0..12 {
+            queue.push(i);
+        }
+        for i in 0..12 {
+            assert_eq!(queue.pop(), Some(i));
+        }
+        assert_eq!(queue.pop(), None);
+        // The sentinel and the first two nodes. The last one is still the head.
+        assert_eq!(queue.reclaimer.retired.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn pusher_that_loses_its_slot_moves_on() {
+        let queue = UnrolledQueue::<u64, Leak, 4>::new();
+        queue.push(1);
+        // A pusher claims the next slot, but stalls before filling it.
+        let tail = unsafe { &*queue.tail.load(Ordering::SeqCst) };
+        let stalled = tail.enq_idx.fetch_add(1, Ordering::SeqCst);
+        queue.push(2);
+
+        // Poppers skip the empty slot, and mark it as given up on.
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), None);
+
+        // When the pusher comes back, it gets its item back to push elsewhere.
+        assert_eq!(tail.slots[stalled].fill(3), Err(3));
+        queue.push(3);
+        assert_eq!(queue.pop(), Some(3));
+    }
+
+    #[test]
+    fn concurrent_no_loss_epoch() {
+        check_concurrent_fifo(&UnrolledQueue::<u64, Epoch, 8>::new(), 4, 4, 20_000, 1);
+    }
+
+    #[test]
+    fn concurrent_no_loss_hazard() {
+        check_concurrent_fifo(&UnrolledQueue::<u64, HazardDomain, 8>::new(), 4, 4, 20_000, 1);
+    }
+}
@@ -0,0 +1,330 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::mem::MaybeUninit;
+use std::cell::UnsafeCell;
+use crossbeam_epoch as epoch;
+use crossbeam_epoch::{Atomic, Owned, Shared, Guard};
+use sync_queue::SyncQueue;
+use thread_id::{self, MAX_THREADS};
+
+/// Marks a node that no dequeuer has claimed yet.
+const NO_THREAD: usize = usize::MAX;
+
+/// Stores data and next pointers for items in the queue
+pub struct Node<T> {
+    // The UnsafeCell wrapper is required to move the T object out of a shared node.
+    // The MaybeUninit wrapper allows for uninitialized (sentinel) nodes.
+    pub data: UnsafeCell<MaybeUninit<T>>,
+    // This pointer to the next node is atomic to allow CAS.
+    pub next: Atomic<Node<T>>,
+    // The thread that pushed this node.
+    pub enq_tid: usize,
+    // The thread whose pop removes this node from the head, once one is chosen.
+    pub deq_tid: AtomicUsize,
+}
+
+impl<T> Node<T> {
+    fn new(data: MaybeUninit<T>, enq_tid: usize) -> Node<T> {
+        Node {
+            data: UnsafeCell::new(data),
+            next: Atomic::null(),
+            enq_tid,
+            deq_tid: AtomicUsize::new(NO_THREAD),
+        }
+    }
+}
+
+/// Describes the pending (or last) operation of a single thread
+// Descriptors are immutable. Threads replace them with CAS, which is how
+// helpers agree on each step of an operation.
+pub struct OpDesc<T> {
+    phase: u64,
+    pending: bool,
+    enqueue: bool,
+    // For a push, the node to link. For a pop, the sentinel being removed.
+    // This does not own the node.
+    node: Atomic<Node<T>>,
+}
+
+impl<T> OpDesc<T> {
+    fn new(phase: u64, pending: bool, enqueue: bool, node: Shared<Node<T>>) -> OpDesc<T> {
+        OpDesc { phase, pending, enqueue, node: Atomic::from(node) }
+    }
+}
+
+/// Wait-free queue based on Kogan and Petrank's design
+// (Wait-Free Queues With Multiple Enqueuers and Dequeuers, PPoPP 2011)
+// This is the Michael-Scott queue with phase-based helping: every operation
+// publishes a descriptor, then helps all operations with an older (or equal)
+// phase finish before returning. No operation can be overtaken forever, so each
+// one completes in a number of steps bounded by the number of threads.
+// Nodes and descriptors are reclaimed with crossbeam's epoch collector.
+pub struct WaitFreeQueue<T> {
+    head: Atomic<Node<T>>,
+    tail: Atomic<Node<T>>,
+    state: Box<[Atomic<OpDesc<T>>]>,
+}
+
+unsafe impl<T: Send> Send for WaitFreeQueue<T> {}
+unsafe impl<T: Send> Sync for WaitFreeQueue<T> {}
+
+impl<T> WaitFreeQueue<T> {
+    pub fn new() -> WaitFreeQueue<T> {
+        let state: Vec<Atomic<OpDesc<T>>> = (0..MAX_THREADS)
+            .map(|_| Atomic::new(OpDesc::new(0, false, true, Shared::null())))
+            .collect();
+        let queue = WaitFreeQueue {
+            head: Atomic::null(),
+            tail: Atomic::null(),
+            state: state.into_boxed_slice(),
+        };
+
+        // Initalize the queue with an empty (sentinel) node to simplify push/pop logic
+        unsafe {
+            let guard = epoch::unprotected();
+            let sentinel = Owned::new(Node::new(MaybeUninit::uninit(), NO_THREAD)).into_shared(guard);
+            queue.head.store(sentinel, Ordering::Relaxed);
+            queue.tail.store(sentinel, Ordering::Relaxed);
+        }
+        queue
+    }
+
+    pub fn push(&self, item: T) {
+        let guard = &epoch::pin(); // enter data structure
+        let tid = thread_id::current();
+        let node = Owned::new(Node::new(MaybeUninit::new(item), tid)).into_shared(guard);
+
+        // Announce our operation, then help everyone up to and including it.
+        let phase = self.max_phase(guard) + 1;
+        self.announce(tid, OpDesc::new(phase, true, true, node), guard);
+        self.help(phase, guard);
+        self.help_finish_enq(guard);
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        let guard = &epoch::pin(); // enter data structure
+        let tid = thread_id::current();
+
+        // Announce our operation, then help everyone up to and including it.
+        let phase = self.max_phase(guard) + 1;
+        self.announce(tid, OpDesc::new(phase, true, false, Shared::null()), guard);
+        self.help(phase, guard);
+        self.help_finish_deq(guard);
+
+        // Our descriptor now holds the sentinel we removed, or null if we found the queue empty.
+        let desc = unsafe { self.state[tid].load(Ordering::SeqCst, guard).deref() };
+        let node = desc.node.load(Ordering::SeqCst, guard);
+        match unsafe { node.as_ref() } {
+            Some(raw_node) => {
+                // The value lives in the new sentinel, which only we may read from.
+                let next = unsafe { raw_node.next.load(Ordering::SeqCst, guard).deref() };
+                Some(unsafe { next.data.get().read().assume_init() })
+            },
+            None => None,
+        }
+    }
+
+    /// Publishes a new descriptor for our own thread.
+    fn announce(&self, tid: usize, desc: OpDesc<T>, guard: &Guard) {
+        let old = self.state[tid].swap(Owned::new(desc), Ordering::SeqCst, guard);
+        unsafe { guard.defer_destroy(old) };
+    }
+
+    /// Replaces thread `tid`'s descriptor if it is still `curr`.
+    fn replace(&self, tid: usize, curr: Shared<OpDesc<T>>, desc: OpDesc<T>, guard: &Guard) -> bool {
+        match self.state[tid].compare_and_set(curr, Owned::new(desc), Ordering::SeqCst, guard) {
+            Ok(_) => {
+                unsafe { guard.defer_destroy(curr) };
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn max_phase(&self, guard: &Guard) -> u64 {
+        self.state[..thread_id::high_water()].iter()
+            .map(|desc| unsafe { desc.load(Ordering::SeqCst, guard).deref().phase })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn is_still_pending(&self, tid: usize, phase: u64, guard: &Guard) -> bool {
+        let desc = unsafe { self.state[tid].load(Ordering::SeqCst, guard).deref() };
+        desc.pending && desc.phase <= phase
+    }
+
+    /// Helps every pending operation with a phase no later than `phase`.
+    fn help(&self, phase: u64, guard: &Guard) {
+        for tid in 0..thread_id::high_water() {
+            let desc = unsafe { self.state[tid].load(Ordering::SeqCst, guard).deref() };
+            if desc.pending && desc.phase <= phase {
+                if desc.enqueue {
+                    self.help_enq(tid, phase, guard);
+                } else {
+                    self.help_deq(tid, phase, guard);
+                }
+            }
+        }
+    }
+
+    fn help_enq(&self, tid: usize, phase: u64, guard: &Guard) {
+        while self.is_still_pending(tid, phase, guard) {
+            let last = self.tail.load(Ordering::SeqCst, guard);
+            let next = unsafe { last.deref() }.next.load(Ordering::SeqCst, guard);
+            if last != self.tail.load(Ordering::SeqCst, guard) {
+                continue
+            }
+
+            if !next.is_null() {
+                // Someone else's node is linked but the tail lags behind, so finish their push.
+                self.help_finish_enq(guard);
+            } else if self.is_still_pending(tid, phase, guard) {
+                // Try to link the node from tid's descriptor.
+                let desc = unsafe { self.state[tid].load(Ordering::SeqCst, guard).deref() };
+                let node = desc.node.load(Ordering::SeqCst, guard);
+                if unsafe { last.deref() }.next.compare_and_set(Shared::null(), node, Ordering::SeqCst, guard).is_ok() {
+                    self.help_finish_enq(guard);
+                    return
+                }
+            }
+        }
+    }
+
+    /// Marks the push of the node after the tail as done, then swings the tail.
+    fn help_finish_enq(&self, guard: &Guard) {
+        let last = self.tail.load(Ordering::SeqCst, guard);
+        let next = unsafe { last.deref() }.next.load(Ordering::SeqCst, guard);
+        if let Some(raw_next) = unsafe { next.as_ref() } {
+            let tid = raw_next.enq_tid;
+            let curr = self.state[tid].load(Ordering::SeqCst, guard);
+            let desc = unsafe { curr.deref() };
+            if last == self.tail.load(Ordering::SeqCst, guard) && desc.node.load(Ordering::SeqCst, guard) == next {
+                self.replace(tid, curr, OpDesc::new(desc.phase, false, true, next), guard);
+                let _ = self.tail.compare_and_set(last, next, Ordering::SeqCst, guard);
+            }
+        }
+    }
+
+    fn help_deq(&self, tid: usize, phase: u64, guard: &Guard) {
+        while self.is_still_pending(tid, phase, guard) {
+            let first = self.head.load(Ordering::SeqCst, guard);
+            let last = self.tail.load(Ordering::SeqCst, guard);
+            let next = unsafe { first.deref() }.next.load(Ordering::SeqCst, guard);
+            if first != self.head.load(Ordering::SeqCst, guard) {
+                continue
+            }
+
+            if first == last {
+                if next.is_null() {
+                    // The queue is empty, so complete the pop with no node.
+                    let curr = self.state[tid].load(Ordering::SeqCst, guard);
+                    let desc = unsafe { curr.deref() };
+                    if last == self.tail.load(Ordering::SeqCst, guard) && desc.pending && desc.phase <= phase {
+                        self.replace(tid, curr, OpDesc::new(desc.phase, false, false, Shared::null()), guard);
+                    }
+                } else {
+                    // A push is in progress, so finish it first.
+                    self.help_finish_enq(guard);
+                }
+            } else {
+                let curr = self.state[tid].load(Ordering::SeqCst, guard);
+                let desc = unsafe { curr.deref() };
+                if !(desc.pending && desc.phase <= phase) {
+                    break
+                }
+
+                // Record the sentinel we are trying to remove in tid's descriptor.
+                if first == self.head.load(Ordering::SeqCst, guard) && desc.node.load(Ordering::SeqCst, guard) != first &&
+                    !self.replace(tid, curr, OpDesc::new(desc.phase, true, false, first), guard) {
+                    continue
+                }
+
+                // Then try to claim it for tid.
+                let _ = unsafe { first.deref() }.deq_tid.compare_exchange(
+                    NO_THREAD, tid, Ordering::SeqCst, Ordering::SeqCst);
+                self.help_finish_deq(guard);
+            }
+        }
+    }
+
+    /// Marks the pop that claimed the sentinel as done, then swings the head.
+    fn help_finish_deq(&self, guard: &Guard) {
+        let first = self.head.load(Ordering::SeqCst, guard);
+        let raw_first = unsafe { first.deref() };
+        let next = raw_first.next.load(Ordering::SeqCst, guard);
+        let tid = raw_first.deq_tid.load(Ordering::SeqCst);
+        if tid == NO_THREAD {
+            return
+        }
+
+        let curr = self.state[tid].load(Ordering::SeqCst, guard);
+        let desc = unsafe { curr.deref() };
+        if first == self.head.load(Ordering::SeqCst, guard) && !next.is_null() {
+            let node = desc.node.load(Ordering::SeqCst, guard);
+            self.replace(tid, curr, OpDesc::new(desc.phase, false, false, node), guard);
+            if self.head.compare_and_set(first, next, Ordering::SeqCst, guard).is_ok() {
+                unsafe { guard.defer_destroy(first) };
+            }
+        }
+    }
+}
+
+impl<T> Default for WaitFreeQueue<T> {
+    fn default() -> WaitFreeQueue<T> {
+        WaitFreeQueue::new()
+    }
+}
+
+impl<T> Drop for WaitFreeQueue<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+        unsafe {
+            let guard = epoch::unprotected();
+            drop(self.head.load(Ordering::SeqCst, guard).into_owned());
+            for desc in self.state.iter() {
+                drop(desc.load(Ordering::SeqCst, guard).into_owned());
+            }
+        }
+    }
+}
+
+impl<T: Send + Sync> SyncQueue<T> for WaitFreeQueue<T> {
+    fn pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn push(&self, elem: T) {
+        self.push(elem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+    use queue_tests::{check_sequential_fifo, check_concurrent_fifo, CountsDrops};
+
+    #[test]
+    fn sequential_fifo() {
+        check_sequential_fifo(&WaitFreeQueue::new(), 1000);
+    }
+
+    #[test]
+    fn concurrent_no_loss() {
+        check_concurrent_fifo(&WaitFreeQueue::new(), 4, 4, 10_000, 1);
+    }
+
+    #[test]
+    fn drop_frees_items_left_in_the_queue() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let queue = WaitFreeQueue::new();
+        for _ in 0..10 {
+            queue.push(CountsDrops(drops.clone()));
+        }
+        drop(queue.pop());
+        drop(queue.pop());
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+        drop(queue);
+        assert_eq!(drops.load(Ordering::SeqCst), 10);
+    }
+}